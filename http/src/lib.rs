@@ -13,7 +13,7 @@ use flate2::Compression;
 use std::io::Write;
 
 /// Contains (key, value) headers
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone)]
 pub struct Headers {
     headers: Vec<Header>,
 }
@@ -28,6 +28,26 @@ impl Headers {
     pub fn iter(&self) -> impl Iterator<Item = &Header> {
         self.headers.iter()
     }
+
+    /// Value of the first header named `key`, ignoring case
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(key))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Replace every header named `key`, ignoring case, with a single one
+    pub fn set(&mut self, key: &str, value: &str) {
+        self.remove(key);
+        self.add(key.to_string(), value.to_string());
+    }
+
+    /// Remove every header named `key`, ignoring case
+    pub fn remove(&mut self, key: &str) {
+        self.headers
+            .retain(|(name, _)| !name.eq_ignore_ascii_case(key));
+    }
 }
 
 pub fn compress_html_into(html: &str, buffer: &mut Vec<u8>) {
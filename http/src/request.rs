@@ -7,14 +7,24 @@ use std::fmt::{self, Display};
 #[derive(Debug, Copy, Clone)]
 pub enum RequestType {
     GET,
+    HEAD,
     POST,
+    PUT,
+    DELETE,
+    OPTIONS,
+    PATCH,
 }
 
 impl Display for RequestType {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         let type_display = match self {
             RequestType::GET => "GET",
+            RequestType::HEAD => "HEAD",
             RequestType::POST => "POST",
+            RequestType::PUT => "PUT",
+            RequestType::DELETE => "DELETE",
+            RequestType::OPTIONS => "OPTIONS",
+            RequestType::PATCH => "PATCH",
         };
 
         write!(f, "{}", type_display)
@@ -28,16 +38,64 @@ impl TryFrom<&str> for RequestType {
     fn try_from(from: &str) -> Result<Self, <Self as TryFrom<&str>>::Error> {
         match from.trim().to_lowercase().as_str() {
             "get" => Ok(RequestType::GET),
+            "head" => Ok(RequestType::HEAD),
             "post" => Ok(RequestType::POST),
+            "put" => Ok(RequestType::PUT),
+            "delete" => Ok(RequestType::DELETE),
+            "options" => Ok(RequestType::OPTIONS),
+            "patch" => Ok(RequestType::PATCH),
             _ => Err(()),
         }
     }
 }
 
+/// HTTP protocol version of a request, e.g. `HTTP/1.1`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct HttpVersion {
+    pub major: u8,
+    pub minor: u8,
+}
+
+impl HttpVersion {
+    pub const HTTP_1_0: HttpVersion = HttpVersion { major: 1, minor: 0 };
+    pub const HTTP_1_1: HttpVersion = HttpVersion { major: 1, minor: 1 };
+
+    /// HTTP/1.1 connections persist unless closed explicitly,
+    /// older versions need to ask for it with `Connection: keep-alive`
+    pub fn persists_by_default(self) -> bool {
+        self >= HttpVersion::HTTP_1_1
+    }
+}
+
+impl Display for HttpVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "HTTP/{}.{}", self.major, self.minor)
+    }
+}
+
+impl TryFrom<&str> for HttpVersion {
+    // Any malformed version is handled the same way, so there is no need for more detail
+    type Error = ();
+
+    fn try_from(from: &str) -> Result<Self, <Self as TryFrom<&str>>::Error> {
+        let from = from.trim();
+        if !from.starts_with("HTTP/") {
+            return Err(());
+        }
+
+        let mut numbers = from["HTTP/".len()..].splitn(2, '.');
+        let major = numbers.next().ok_or(())?.parse().map_err(|_| ())?;
+        let minor = numbers.next().ok_or(())?.parse().map_err(|_| ())?;
+
+        Ok(HttpVersion { major, minor })
+    }
+}
+
 /// HTTP request
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Request {
     request_type: RequestType,
+    version: HttpVersion,
     /// This should either be an IP or resolve to one
     host: String,
     /// Port to send the request too.
@@ -49,6 +107,37 @@ pub struct Request {
     headers: Headers,
 }
 
+impl Request {
+    pub fn request_type(&self) -> RequestType {
+        self.request_type
+    }
+
+    pub fn version(&self) -> HttpVersion {
+        self.version
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Change the path, e.g. to rewrite it before the request is routed
+    pub fn set_path(&mut self, path: &str) {
+        self.path = path.to_string();
+    }
+
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    pub fn headers_mut(&mut self) -> &mut Headers {
+        &mut self.headers
+    }
+}
+
 /// Builds an HTTP request
 pub struct RequestBuilder {
     request: Request,
@@ -59,6 +148,7 @@ impl RequestBuilder {
         Self {
             request: Request {
                 request_type,
+                version: HttpVersion::HTTP_1_1,
                 host: host.to_string(),
                 port: 80,
                 path: "/".to_string(),
@@ -67,6 +157,16 @@ impl RequestBuilder {
         }
     }
 
+    pub fn version(&mut self, version: HttpVersion) -> &mut Self {
+        self.request.version = version;
+        self
+    }
+
+    pub fn host(&mut self, host: &str) -> &mut Self {
+        self.request.host = host.to_string();
+        self
+    }
+
     pub fn port(&mut self, port: usize) -> &mut Self {
         self.request.port = port;
         self
@@ -88,3 +188,25 @@ impl RequestBuilder {
         self.request
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_version() {
+        assert_eq!(HttpVersion::try_from("HTTP/1.1"), Ok(HttpVersion::HTTP_1_1));
+        assert_eq!(
+            HttpVersion::try_from("HTTP/2.0"),
+            Ok(HttpVersion { major: 2, minor: 0 })
+        );
+        assert_eq!(HttpVersion::try_from("HTTP/1"), Err(()));
+        assert_eq!(HttpVersion::try_from("FTP/1.1"), Err(()));
+    }
+
+    #[test]
+    fn version_persistence() {
+        assert!(HttpVersion::HTTP_1_1.persists_by_default());
+        assert!(!HttpVersion::HTTP_1_0.persists_by_default());
+    }
+}
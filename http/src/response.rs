@@ -97,9 +97,58 @@ impl Response {
         }
     }
 
+    /// Parse the head of a response, every line except the last empty one, e.g. the head given
+    /// to a response writer
+    /// Returns `None` if the head has no valid status line
+    pub fn from_head(head: &[u8], body: Vec<u8>) -> Option<Self> {
+        let head = std::str::from_utf8(head).ok()?;
+        let mut lines = head.split("\r\n").filter(|line| !line.is_empty());
+
+        let status_line = lines.next()?;
+        if !status_line.starts_with("HTTP/") {
+            return None;
+        }
+        let code = status_line.splitn(2, ' ').nth(1)?;
+
+        let mut headers = Headers::default();
+        for line in lines {
+            let split = line.find(':')?;
+            let (name, value) = line.split_at(split);
+            headers.add(name.trim().to_string(), value[1..].trim().to_string());
+        }
+
+        Some(Self {
+            headers,
+            code: code.to_string(),
+            body,
+        })
+    }
+
+    /// Code such as "404 NOT FOUND"
+    pub fn code(&self) -> &str {
+        &self.code
+    }
+
+    pub fn set_code(&mut self, code: &str) {
+        self.code = code.to_string();
+    }
+
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    pub fn headers_mut(&mut self) -> &mut Headers {
+        &mut self.headers
+    }
+
     pub fn body(&self) -> &Vec<u8> {
         &self.body
     }
+
+    pub fn body_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.body
+    }
+
     pub fn head_bytes(&self) -> Vec<u8> {
         let mut head = Vec::new();
 
@@ -146,3 +195,30 @@ impl ResponseBuilder {
         self.response
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_head() {
+        let head = response_head!(
+            "404 NOT FOUND",
+            h("Content-Type" => "text/html"),
+            h("Cache-Control" => "public")
+        );
+        let mut response = Response::from_head(head.as_bytes(), b"missing".to_vec()).unwrap();
+
+        assert_eq!(response.code(), "404 NOT FOUND");
+        assert_eq!(response.headers().get("content-type"), Some("text/html"));
+
+        response.headers_mut().set("cache-control", "no-store");
+        assert_eq!(
+            response.head_bytes(),
+            b"HTTP/1.1 404 NOT FOUND\r\nContent-Type:text/html\r\ncache-control:no-store\r\n"
+                .to_vec()
+        );
+
+        assert!(Response::from_head(b"not a response", Vec::new()).is_none());
+    }
+}
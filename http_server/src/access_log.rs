@@ -0,0 +1,262 @@
+use chrono::{DateTime, Local};
+use std::fmt::Write as FmtWrite;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Format of access log lines
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AccessLogFormat {
+    /// Common Log Format: `host - - [time] "request" status bytes`
+    Common,
+    /// Common Log Format followed by the quoted referer and user agent
+    Combined,
+    /// One JSON object per line
+    Json,
+}
+
+/// When the access log file is rotated
+///
+/// A rotated file is renamed with the time it was rotated at, e.g. `access.log.20181020-153000`,
+/// and a new file is started.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Rotation {
+    /// Rotate once the file is bigger than this many bytes
+    pub max_size: Option<u64>,
+    /// Rotate once the file has been written to for this long
+    pub interval: Option<Duration>,
+}
+
+/// A request that was served, as written to the access log
+#[derive(Debug)]
+pub(crate) struct AccessEntry<'a> {
+    /// `None` for clients connected through a unix socket
+    pub remote: Option<SocketAddr>,
+    pub time: DateTime<Local>,
+    pub request_id: &'a str,
+    /// Method as sent by the client
+    pub method: &'a str,
+    pub path: &'a str,
+    pub version: &'a str,
+    /// `None` if the endpoint did not write a response
+    pub status: Option<u16>,
+    /// Bytes written to the client, head included
+    pub bytes: usize,
+    pub referer: Option<&'a str>,
+    pub user_agent: Option<&'a str>,
+    pub duration: Duration,
+}
+
+/// Writes a line to a file for every request served
+pub struct AccessLog {
+    format: AccessLogFormat,
+    file: Mutex<LogFile>,
+}
+
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    opened: Instant,
+    rotation: Rotation,
+}
+
+impl AccessLog {
+    /// Open (or create) the access log at `path`, lines are appended to an existing file
+    pub fn open(
+        path: impl Into<PathBuf>,
+        format: AccessLogFormat,
+        rotation: Rotation,
+    ) -> io::Result<Self> {
+        let path = path.into();
+        let file = open_append(&path)?;
+        let size = file.metadata()?.len();
+
+        Ok(Self {
+            format,
+            file: Mutex::new(LogFile {
+                path,
+                file,
+                size,
+                opened: Instant::now(),
+                rotation,
+            }),
+        })
+    }
+
+    pub(crate) fn log(&self, entry: &AccessEntry) {
+        let line = format_entry(self.format, entry);
+
+        let mut file = self.file.lock().unwrap();
+        if let Err(err) = file.write_line(&line) {
+            error!(error:% = err; "Could not write to the access log");
+        }
+    }
+}
+
+impl LogFile {
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if self.needs_rotation() {
+            self.rotate()?;
+        }
+
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn needs_rotation(&self) -> bool {
+        let too_big = self
+            .rotation
+            .max_size
+            .map_or(false, |max_size| self.size >= max_size);
+        let too_old = self
+            .rotation
+            .interval
+            .map_or(false, |interval| self.opened.elapsed() >= interval);
+
+        too_big || too_old
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let mut rotated = self.path.clone().into_os_string();
+        rotated.push(Local::now().format(".%Y%m%d-%H%M%S").to_string());
+
+        fs::rename(&self.path, rotated)?;
+        self.file = open_append(&self.path)?;
+        self.size = 0;
+        self.opened = Instant::now();
+        Ok(())
+    }
+}
+
+fn open_append(path: &PathBuf) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn format_entry(format: AccessLogFormat, entry: &AccessEntry) -> String {
+    let remote = entry
+        .remote
+        .map(|addr| addr.ip().to_string())
+        .unwrap_or_else(|| "-".to_string());
+    let status = entry
+        .status
+        .map(|status| status.to_string())
+        .unwrap_or_else(|| "-".to_string());
+
+    let mut line = String::new();
+    match format {
+        AccessLogFormat::Common | AccessLogFormat::Combined => {
+            let _ = write!(
+                line,
+                "{} - - [{}] \"{} {} {}\" {} {}",
+                remote,
+                entry.time.format("%d/%b/%Y:%H:%M:%S %z"),
+                escape(entry.method),
+                escape(entry.path),
+                escape(entry.version),
+                status,
+                entry.bytes
+            );
+
+            if format == AccessLogFormat::Combined {
+                let _ = write!(
+                    line,
+                    " \"{}\" \"{}\"",
+                    escape(entry.referer.unwrap_or("-")),
+                    escape(entry.user_agent.unwrap_or("-"))
+                );
+            }
+        }
+        AccessLogFormat::Json => {
+            let _ = write!(
+                line,
+                "{{\"time\":\"{}\",\"request_id\":\"{}\",\"remote_addr\":\"{}\",\"method\":\"{}\",\"path\":\"{}\",\
+                 \"version\":\"{}\",\"status\":{},\"bytes\":{},\"referer\":\"{}\",\
+                 \"user_agent\":\"{}\",\"duration_ms\":{:.3}}}",
+                entry.time.to_rfc3339(),
+                escape(entry.request_id),
+                remote,
+                escape(entry.method),
+                escape(entry.path),
+                escape(entry.version),
+                entry.status.map_or(0, u32::from),
+                entry.bytes,
+                escape(entry.referer.unwrap_or("")),
+                escape(entry.user_agent.unwrap_or("")),
+                duration_ms(entry.duration)
+            );
+        }
+    }
+
+    line.push('\n');
+    line
+}
+
+fn duration_ms(duration: Duration) -> f64 {
+    duration.as_secs() as f64 * 1000.0 + f64::from(duration.subsec_nanos()) / 1_000_000.0
+}
+
+/// Escape quotes, backslashes and control characters, which are valid in both quoted log fields
+/// and JSON strings
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn entry() -> AccessEntry<'static> {
+        AccessEntry {
+            remote: Some("127.0.0.1:5000".parse().unwrap()),
+            time: Local.ymd(2018, 10, 20).and_hms(15, 30, 0),
+            request_id: "5f3a9c1e-0000002a",
+            method: "GET",
+            path: "/index.html",
+            version: "HTTP/1.1",
+            status: Some(200),
+            bytes: 1024,
+            referer: None,
+            user_agent: Some("curl/7.61 \"test\""),
+            duration: Duration::from_millis(12),
+        }
+    }
+
+    #[test]
+    fn format_common_and_combined() {
+        let common = format_entry(AccessLogFormat::Common, &entry());
+        assert!(common.starts_with("127.0.0.1 - - [20/Oct/2018:15:30:00 "));
+        assert!(common.ends_with("] \"GET /index.html HTTP/1.1\" 200 1024\n"));
+
+        let combined = format_entry(AccessLogFormat::Combined, &entry());
+        assert!(combined.ends_with("200 1024 \"-\" \"curl/7.61 \\\"test\\\"\"\n"));
+    }
+
+    #[test]
+    fn format_json() {
+        let json = format_entry(AccessLogFormat::Json, &entry());
+        assert!(json.contains("\"request_id\":\"5f3a9c1e-0000002a\",\"remote_addr\":\"127.0.0.1\""));
+        assert!(json.contains("\"remote_addr\":\"127.0.0.1\",\"method\":\"GET\""));
+        assert!(json.contains("\"status\":200,\"bytes\":1024,\"referer\":\"\""));
+        assert!(json.contains("\"user_agent\":\"curl/7.61 \\\"test\\\"\""));
+        assert!(json.ends_with("\"duration_ms\":12.000}\n"));
+    }
+}
@@ -0,0 +1,394 @@
+use crate::listener::Stream;
+use crate::HttpServerError;
+use http::Headers;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Longest chunk size or trailer line accepted in a chunked body
+const MAX_CHUNK_LINE: u64 = 1024;
+
+/// Most trailer lines accepted after the last chunk
+const MAX_TRAILERS: usize = 100;
+
+/// How the end of a message body is found (RFC 7230 section 3.3.3)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Framing {
+    /// The body is this many bytes long
+    Length(u64),
+    /// The body is sent in chunks, the last one being empty
+    Chunked,
+    /// The body goes on until the connection is closed, only for responses
+    UntilClose,
+}
+
+impl Framing {
+    /// Framing given by the `Transfer-Encoding` and `Content-Length` headers, `default` if there
+    /// are neither
+    pub fn of(headers: &Headers, default: Framing) -> Result<Self, HttpServerError> {
+        let encodings: Vec<&str> = headers
+            .iter()
+            .filter(|(name, _)| name.eq_ignore_ascii_case("transfer-encoding"))
+            .flat_map(|(_, value)| value.split(','))
+            .map(str::trim)
+            .filter(|encoding| !encoding.is_empty())
+            .collect();
+
+        // Transfer-Encoding wins over Content-Length, and chunked needs to be the last encoding
+        if let Some(last) = encodings.last() {
+            return if last.eq_ignore_ascii_case("chunked") && encodings.len() == 1 {
+                Ok(Framing::Chunked)
+            } else {
+                Err(HttpServerError::UnsupportedTransferEncoding)
+            };
+        }
+
+        let mut length = None;
+        for (_, value) in headers
+            .iter()
+            .filter(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+        {
+            let value = value.trim();
+            if value.is_empty() || !value.bytes().all(|byte| byte.is_ascii_digit()) {
+                return Err(HttpServerError::InvalidContentLength);
+            }
+            let value = value
+                .parse()
+                .map_err(|_| HttpServerError::InvalidContentLength)?;
+
+            // Repeated lengths are only accepted if they agree
+            if length.map_or(false, |length| length != value) {
+                return Err(HttpServerError::InvalidContentLength);
+            }
+            length = Some(value);
+        }
+
+        Ok(length.map_or(default, Framing::Length))
+    }
+}
+
+/// Where a decoder is in a body
+#[derive(Debug, Clone, Copy)]
+enum State {
+    /// Bytes left in a body of known length
+    Length(u64),
+    ChunkSize,
+    /// Bytes left in the current chunk
+    Chunk(u64),
+    /// The line ending after the data of a chunk
+    ChunkEnd,
+    UntilClose,
+    Done,
+}
+
+/// Reads the data of a body from the message's reader, without reading past its end
+#[derive(Debug)]
+pub(crate) struct Decoder {
+    state: State,
+}
+
+impl Decoder {
+    pub fn new(framing: Framing) -> Self {
+        let state = match framing {
+            Framing::Length(length) => State::Length(length),
+            Framing::Chunked => State::ChunkSize,
+            Framing::UntilClose => State::UntilClose,
+        };
+
+        Self { state }
+    }
+
+    /// Whether the whole body was read
+    pub fn is_done(&self) -> bool {
+        match self.state {
+            State::Length(0) | State::Done => true,
+            _ => false,
+        }
+    }
+
+    /// Read body data into `buf`, 0 once the body is done
+    pub fn read(&mut self, reader: &mut impl BufRead, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            match self.state {
+                State::Length(0) | State::Done => return Ok(0),
+                State::Length(remaining) => {
+                    let read = read_data(reader, buf, remaining)?;
+                    self.state = State::Length(remaining - read as u64);
+                    return Ok(read);
+                }
+                State::ChunkSize => {
+                    let line = read_chunk_line(reader)?;
+                    // Chunk extensions come after a ';' and are ignored
+                    let size = line.split(';').next().unwrap_or("").trim();
+                    let size = u64::from_str_radix(size, 16)
+                        .map_err(|_| invalid_data("invalid chunk size"))?;
+
+                    self.state = if size == 0 {
+                        read_trailers(reader)?;
+                        State::Done
+                    } else {
+                        State::Chunk(size)
+                    };
+                }
+                State::Chunk(remaining) => {
+                    let read = read_data(reader, buf, remaining)?;
+                    self.state = if read as u64 == remaining {
+                        State::ChunkEnd
+                    } else {
+                        State::Chunk(remaining - read as u64)
+                    };
+                    return Ok(read);
+                }
+                State::ChunkEnd => {
+                    if !read_chunk_line(reader)?.is_empty() {
+                        return Err(invalid_data("chunk longer than its size"));
+                    }
+                    self.state = State::ChunkSize;
+                }
+                State::UntilClose => {
+                    let read = reader.read(buf)?;
+                    if read == 0 {
+                        self.state = State::Done;
+                    }
+                    return Ok(read);
+                }
+            }
+        }
+    }
+}
+
+/// Read at most `remaining` bytes of data, failing if the connection ends first
+fn read_data(reader: &mut impl BufRead, buf: &mut [u8], remaining: u64) -> io::Result<usize> {
+    let max = if remaining < buf.len() as u64 {
+        remaining as usize
+    } else {
+        buf.len()
+    };
+
+    match reader.read(&mut buf[..max])? {
+        0 => Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "the connection ended before the end of the body",
+        )),
+        read => Ok(read),
+    }
+}
+
+/// Read a line of a chunked body, without its line ending
+fn read_chunk_line(reader: &mut impl BufRead) -> io::Result<String> {
+    let mut line = Vec::new();
+    reader
+        .by_ref()
+        .take(MAX_CHUNK_LINE)
+        .read_until(b'\n', &mut line)?;
+
+    if !line.ends_with(b"\n") {
+        return Err(invalid_data("chunk line too long or cut short"));
+    }
+
+    Ok(String::from_utf8_lossy(&line).trim().to_string())
+}
+
+/// Skip the trailer headers that can follow the last chunk
+fn read_trailers(reader: &mut impl BufRead) -> io::Result<()> {
+    for _ in 0..=MAX_TRAILERS {
+        if read_chunk_line(reader)?.is_empty() {
+            return Ok(());
+        }
+    }
+
+    Err(invalid_data("too many trailers"))
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// The connection's reader while an endpoint can read the body of its request
+pub(crate) type BodySlot = Arc<Mutex<Option<BodySource>>>;
+
+/// Reads a request body from the client's connection
+#[derive(Debug)]
+pub(crate) struct BodySource {
+    /// The connection's reader, which may hold the start of the body and the next requests
+    reader: BufReader<Stream>,
+    decoder: Decoder,
+    read_timeout: Duration,
+    /// The client waits for a `100 Continue` before sending the body
+    expects_continue: bool,
+    started: bool,
+}
+
+impl BodySource {
+    /// Skip what the endpoint did not read of the body, reading at most `limit` bytes
+    /// Returns the connection's reader, with whether the whole body was skipped
+    pub fn finish(mut self, limit: u64) -> (BufReader<Stream>, bool) {
+        // The client was never told to send the body, it may or may not send it
+        if self.expects_continue && !self.started {
+            return (self.reader, false);
+        }
+
+        let skipped = io::copy(&mut self.by_ref().take(limit), &mut io::sink()).is_ok();
+        let done = skipped && self.decoder.is_done();
+        (self.reader, done)
+    }
+}
+
+impl Read for BodySource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.started {
+            self.started = true;
+            self.reader
+                .get_ref()
+                .set_read_timeout(Some(self.read_timeout))?;
+
+            if self.expects_continue {
+                self.reader
+                    .get_mut()
+                    .write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+            }
+        }
+
+        self.decoder.read(&mut self.reader, buf)
+    }
+}
+
+/// Body of a request, read from the client as the endpoint reads it
+///
+/// The body can only be read while the request is handled. Whatever the endpoint does not read
+/// is skipped afterwards, up to a limit above which the connection is closed instead.
+#[derive(Debug)]
+pub struct RequestBody {
+    framing: Framing,
+    /// `None` if the request has no body
+    source: Option<BodySlot>,
+}
+
+impl RequestBody {
+    pub(crate) fn empty() -> Self {
+        Self {
+            framing: Framing::Length(0),
+            source: None,
+        }
+    }
+
+    /// Body read from `reader`, which is handed back through the returned slot once the request
+    /// is done
+    /// Each read waits at most `read_timeout` for the client
+    pub(crate) fn new(
+        reader: BufReader<Stream>,
+        framing: Framing,
+        read_timeout: Duration,
+        expects_continue: bool,
+    ) -> (Self, BodySlot) {
+        let source = Arc::new(Mutex::new(Some(BodySource {
+            reader,
+            decoder: Decoder::new(framing),
+            read_timeout,
+            expects_continue,
+            started: false,
+        })));
+
+        let body = Self {
+            framing,
+            source: Some(source.clone()),
+        };
+        (body, source)
+    }
+
+    /// Length of the body given by the client, `None` if it is sent in chunks
+    pub fn content_length(&self) -> Option<u64> {
+        match self.framing {
+            Framing::Length(length) => Some(length),
+            Framing::Chunked | Framing::UntilClose => None,
+        }
+    }
+}
+
+impl Read for RequestBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let source = match &self.source {
+            Some(source) => source,
+            None => return Ok(0),
+        };
+
+        match source.lock().unwrap().as_mut() {
+            Some(source) => source.read(buf),
+            None => Err(io::Error::new(
+                io::ErrorKind::Other,
+                "the request is done, its body can no longer be read",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(framing: Framing, message: &[u8]) -> io::Result<(Vec<u8>, Vec<u8>)> {
+        let mut reader = io::Cursor::new(message);
+        let mut decoder = Decoder::new(framing);
+        let mut body = Vec::new();
+        let mut buf = [0; 3];
+
+        loop {
+            match decoder.read(&mut reader, &mut buf)? {
+                0 => break,
+                read => body.extend_from_slice(&buf[..read]),
+            }
+        }
+
+        let rest = message[reader.position() as usize..].to_vec();
+        Ok((body, rest))
+    }
+
+    #[test]
+    fn decode_bodies() {
+        let (body, rest) = decode(Framing::Length(5), b"hello, next request").unwrap();
+        assert_eq!(
+            (&body[..], &rest[..]),
+            (&b"hello"[..], &b", next request"[..])
+        );
+
+        let chunked = b"4\r\nWiki\r\n7;name=value\r\npedia i\r\n0\r\nExpires: never\r\n\r\nGET /";
+        let (body, rest) = decode(Framing::Chunked, chunked).unwrap();
+        assert_eq!((&body[..], &rest[..]), (&b"Wikipedia i"[..], &b"GET /"[..]));
+
+        let (body, _) = decode(Framing::UntilClose, b"to the end").unwrap();
+        assert_eq!(body, b"to the end");
+
+        assert!(decode(Framing::Length(10), b"short").is_err());
+        assert!(decode(Framing::Chunked, b"zz\r\nnot hex\r\n").is_err());
+        assert!(decode(Framing::Chunked, b"2\r\ntoo long\r\n0\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn framing_from_headers() {
+        let headers = |pairs: &[(&str, &str)]| {
+            let mut headers = Headers::default();
+            for (name, value) in pairs {
+                headers.add(name.to_string(), value.to_string());
+            }
+            headers
+        };
+        let none = Framing::Length(0);
+
+        assert_eq!(Framing::of(&headers(&[]), none).ok(), Some(none));
+        let length = headers(&[("Content-Length", "42"), ("content-length", "42")]);
+        assert_eq!(Framing::of(&length, none).ok(), Some(Framing::Length(42)));
+        let chunked = headers(&[("Transfer-Encoding", "chunked"), ("Content-Length", "42")]);
+        assert_eq!(Framing::of(&chunked, none).ok(), Some(Framing::Chunked));
+
+        assert!(Framing::of(&headers(&[("Content-Length", "-1")]), none).is_err());
+        assert!(Framing::of(&headers(&[("Content-Length", "1, 2")]), none).is_err());
+        let conflicting = headers(&[("Content-Length", "1"), ("Content-Length", "2")]);
+        assert!(Framing::of(&conflicting, none).is_err());
+        let gzip = headers(&[("Transfer-Encoding", "gzip, chunked")]);
+        assert!(Framing::of(&gzip, none).is_err());
+    }
+}
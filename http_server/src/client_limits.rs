@@ -0,0 +1,330 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How often clients that are back to their full allowance are forgotten
+const PRUNE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Limits applied to every client address
+///
+/// IPv4 clients are limited by address, IPv6 clients by the network of their `ipv6_prefix`
+/// leading bits, since a single client usually gets a whole /64.
+#[derive(Debug, Clone)]
+pub struct ClientLimits {
+    /// Most connections open at once from a client, more are refused with a 429
+    pub max_connections: Option<usize>,
+    /// Requests a client can make per second on average, more are refused with a 429
+    pub requests_per_second: Option<f64>,
+    /// Requests a client can make in a burst above its rate
+    pub burst: u32,
+    pub ipv6_prefix: u8,
+    /// Clients that are never limited
+    pub allowlist: Vec<Network>,
+}
+
+impl Default for ClientLimits {
+    fn default() -> Self {
+        Self {
+            max_connections: None,
+            requests_per_second: None,
+            burst: 10,
+            ipv6_prefix: 64,
+            allowlist: Vec::new(),
+        }
+    }
+}
+
+/// An address with the number of its leading bits that are significant, e.g. "10.0.0.0/8"
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Network {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Network {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, canonical_ip(ip)) {
+            (IpAddr::V4(_), IpAddr::V4(_)) | (IpAddr::V6(_), IpAddr::V6(_)) => {
+                mask(ip, self.prefix) == self.addr
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Network {
+    type Err = InvalidNetwork;
+
+    /// Parse a network, or a single address
+    fn from_str(network: &str) -> Result<Self, Self::Err> {
+        let mut parts = network.splitn(2, '/');
+        let addr: IpAddr = parts
+            .next()
+            .and_then(|addr| addr.parse().ok())
+            .ok_or(InvalidNetwork)?;
+        let addr = canonical_ip(addr);
+
+        let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match parts.next() {
+            Some(prefix) => prefix.parse().map_err(|_| InvalidNetwork)?,
+            None => max_prefix,
+        };
+        if prefix > max_prefix {
+            return Err(InvalidNetwork);
+        }
+
+        Ok(Self {
+            addr: mask(addr, prefix),
+            prefix,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidNetwork;
+
+impl fmt::Display for InvalidNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid network, expected an address or e.g. 10.0.0.0/8")
+    }
+}
+
+/// IPv4 clients of dual-stack listeners are seen as IPv4-mapped IPv6 addresses
+pub(crate) fn canonical_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(ip) => ip.to_ipv4().map_or(IpAddr::V6(ip), IpAddr::V4),
+        ip => ip,
+    }
+}
+
+/// Keep the `prefix` leading bits of an address
+fn mask(ip: IpAddr, prefix: u8) -> IpAddr {
+    match canonical_ip(ip) {
+        IpAddr::V4(ip) => {
+            let mask = u32::max_value()
+                .checked_shl(32 - u32::from(prefix.min(32)))
+                .unwrap_or(0);
+            IpAddr::V4(Ipv4Addr::from(u32::from(ip) & mask))
+        }
+        IpAddr::V6(ip) => {
+            let mask = u128::max_value()
+                .checked_shl(128 - u32::from(prefix.min(128)))
+                .unwrap_or(0);
+            IpAddr::V6(Ipv6Addr::from(u128::from(ip) & mask))
+        }
+    }
+}
+
+/// What is known of a client
+#[derive(Debug)]
+struct Client {
+    connections: usize,
+    /// Requests the client can make right away
+    tokens: f64,
+    refilled: Instant,
+}
+
+/// Connections and request rates of every client
+#[derive(Debug, Default)]
+pub(crate) struct ClientTracker {
+    limits: ClientLimits,
+    clients: Mutex<Clients>,
+}
+
+#[derive(Debug, Default)]
+struct Clients {
+    by_key: HashMap<IpAddr, Client>,
+    pruned: Option<Instant>,
+}
+
+/// Counts a connection as open for its client until dropped
+pub(crate) struct ClientConnection {
+    tracker: Arc<ClientTracker>,
+    key: IpAddr,
+}
+
+impl Drop for ClientConnection {
+    fn drop(&mut self) {
+        let mut clients = self.tracker.clients.lock().unwrap();
+        if let Some(client) = clients.by_key.get_mut(&self.key) {
+            client.connections -= 1;
+        }
+    }
+}
+
+impl ClientTracker {
+    pub fn new(limits: ClientLimits) -> Self {
+        Self {
+            limits,
+            clients: Mutex::default(),
+        }
+    }
+
+    /// The client a limit applies to, `None` if the client is not limited
+    fn key(&self, ip: IpAddr) -> Option<IpAddr> {
+        let ip = canonical_ip(ip);
+        if self
+            .limits
+            .allowlist
+            .iter()
+            .any(|network| network.contains(ip))
+        {
+            return None;
+        }
+
+        match ip {
+            IpAddr::V4(_) => Some(ip),
+            IpAddr::V6(_) => Some(mask(ip, self.limits.ipv6_prefix)),
+        }
+    }
+
+    /// Count a new connection of `ip`
+    /// Returns `Err(())` if the client already has as many connections as it is allowed
+    pub fn connection_opened(
+        tracker: &Arc<ClientTracker>,
+        ip: IpAddr,
+    ) -> Result<Option<ClientConnection>, ()> {
+        let key = match (tracker.limits.max_connections, tracker.key(ip)) {
+            (Some(_), Some(key)) => key,
+            _ => return Ok(None),
+        };
+
+        let mut clients = tracker.clients.lock().unwrap();
+        let client = tracker.client(&mut clients, key);
+        if Some(client.connections) >= tracker.limits.max_connections {
+            return Err(());
+        }
+        client.connections += 1;
+
+        Ok(Some(ClientConnection {
+            tracker: tracker.clone(),
+            key,
+        }))
+    }
+
+    /// Take a token from the client's bucket
+    /// Returns how long the client has to wait for its next token if it has none
+    pub fn request(&self, ip: IpAddr) -> Result<(), Duration> {
+        let (rate, key) = match (self.limits.requests_per_second, self.key(ip)) {
+            (Some(rate), Some(key)) => (rate, key),
+            _ => return Ok(()),
+        };
+
+        let mut clients = self.clients.lock().unwrap();
+        let burst = f64::from(self.limits.burst.max(1));
+        let client = self.client(&mut clients, key);
+
+        let now = Instant::now();
+        client.tokens = (client.tokens + seconds(now - client.refilled) * rate).min(burst);
+        client.refilled = now;
+
+        if client.tokens >= 1.0 {
+            client.tokens -= 1.0;
+            Ok(())
+        } else {
+            let wait = (1.0 - client.tokens) / rate;
+            Err(Duration::from_millis((wait * 1000.0).ceil() as u64))
+        }
+    }
+
+    fn client<'a>(&self, clients: &'a mut Clients, key: IpAddr) -> &'a mut Client {
+        let burst = f64::from(self.limits.burst.max(1));
+        clients.by_key.entry(key).or_insert_with(|| Client {
+            connections: 0,
+            tokens: burst,
+            refilled: Instant::now(),
+        })
+    }
+
+    /// Forget clients without connections whose bucket is full again
+    /// Does nothing if clients were pruned recently
+    pub fn prune(&self) {
+        let mut clients = self.clients.lock().unwrap();
+        if clients
+            .pruned
+            .map_or(false, |pruned| pruned.elapsed() < PRUNE_INTERVAL)
+        {
+            return;
+        }
+        clients.pruned = Some(Instant::now());
+
+        let burst = f64::from(self.limits.burst.max(1));
+        let rate = self.limits.requests_per_second.unwrap_or(0.0);
+        clients.by_key.retain(|_, client| {
+            let tokens = client.tokens + seconds(client.refilled.elapsed()) * rate;
+            client.connections > 0 || (rate > 0.0 && tokens < burst)
+        });
+    }
+}
+
+fn seconds(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1e9
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn networks() {
+        let network: Network = "10.0.0.0/8".parse().unwrap();
+        assert!(network.contains(ip("10.20.30.40")));
+        assert!(network.contains(ip("::ffff:10.0.0.1")));
+        assert!(!network.contains(ip("11.0.0.1")));
+        assert!(!network.contains(ip("::1")));
+
+        let network: Network = "2001:db8::/32".parse().unwrap();
+        assert!(network.contains(ip("2001:db8:1::1")));
+        assert!(!network.contains(ip("2001:db9::1")));
+
+        assert!("::1".parse::<Network>().unwrap().contains(ip("::1")));
+        assert!("10.0.0.0/33".parse::<Network>().is_err());
+        assert!("localhost".parse::<Network>().is_err());
+    }
+
+    #[test]
+    fn connection_cap() {
+        let tracker = Arc::new(ClientTracker::new(ClientLimits {
+            max_connections: Some(2),
+            allowlist: vec!["127.0.0.1".parse().unwrap()],
+            ..ClientLimits::default()
+        }));
+
+        let first = ClientTracker::connection_opened(&tracker, ip("2001:db8::1")).unwrap();
+        let _second = ClientTracker::connection_opened(&tracker, ip("2001:db8::2")).unwrap();
+        // Same /64
+        assert!(ClientTracker::connection_opened(&tracker, ip("2001:db8::3")).is_err());
+        assert!(ClientTracker::connection_opened(&tracker, ip("2001:db8:0:1::1")).is_ok());
+
+        drop(first);
+        assert!(ClientTracker::connection_opened(&tracker, ip("2001:db8::3")).is_ok());
+
+        for _ in 0..3 {
+            let allowed = ClientTracker::connection_opened(&tracker, ip("127.0.0.1")).unwrap();
+            assert!(allowed.is_none());
+        }
+    }
+
+    #[test]
+    fn rate_limit() {
+        let tracker = ClientTracker::new(ClientLimits {
+            requests_per_second: Some(1.0),
+            burst: 2,
+            ..ClientLimits::default()
+        });
+
+        assert!(tracker.request(ip("192.0.2.1")).is_ok());
+        assert!(tracker.request(ip("192.0.2.1")).is_ok());
+        let wait = tracker.request(ip("192.0.2.1")).unwrap_err();
+        assert!(wait > Duration::from_millis(900) && wait <= Duration::from_secs(1));
+
+        assert!(tracker.request(ip("192.0.2.2")).is_ok());
+    }
+}
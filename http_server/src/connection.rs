@@ -0,0 +1,787 @@
+use crate::access_log::AccessEntry;
+use crate::body::{Framing, RequestBody};
+use crate::client_limits::{canonical_ip, ClientConnection};
+use crate::listener::Stream;
+use crate::metrics::{Metrics, OpenConnection};
+use crate::middleware::{Flow, ResponseFilters};
+use crate::panics;
+use crate::request_id::{self, CurrentRequest, REQUEST_ID_HEADER};
+use crate::response_writer::{KeepAlive, ResponseWriter};
+use crate::virtual_hosts::normalize_host;
+use crate::{HttpRouteInfo, HttpServerError, ServerState};
+use chrono::Local;
+use http::{HttpVersion, RequestBuilder, RequestType, ResponseBuilder};
+use std::convert::TryFrom;
+use std::io::{self, BufRead, BufReader};
+use std::mem;
+use std::net::IpAddr;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Most bytes of a request body left unread by the endpoint that are skipped to keep the
+/// connection open, the connection is closed if more are left
+const MAX_SKIPPED_BODY: u64 = 64 * 1024;
+
+/// Settings for client connections
+#[derive(Debug, Clone)]
+pub struct ConnectionSettings {
+    /// How long a client has to finish sending a request once it started sending it
+    pub request_read_timeout: Duration,
+    /// How long a client has to send the request line and every header, however slowly they trickle in
+    pub header_read_timeout: Duration,
+    /// How long an idle persistent connection is kept open while waiting for the next request
+    pub keep_alive_timeout: Duration,
+    /// Maximum number of requests served on a single connection
+    pub max_requests: usize,
+    /// Longest request line accepted, in bytes, longer ones are refused with a 414
+    pub max_request_line: usize,
+    /// Most bytes of headers accepted in a request, more are refused with a 431
+    pub max_header_size: usize,
+    /// Most headers accepted in a request, more are refused with a 431
+    pub max_headers: usize,
+    /// Connections waiting for a worker above which new ones are refused with a 503
+    pub max_queued: usize,
+    /// Workers can be added while connections wait for one, up to this many
+    /// `None` keeps the number of workers given to `HttpServer::listen`
+    pub max_workers: Option<usize>,
+    /// How long workers above the number given to `HttpServer::listen` stay idle before retiring
+    pub worker_idle_timeout: Duration,
+    /// Stack size of the worker threads in bytes, the default of `std::thread` if not set
+    pub worker_stack_size: Option<usize>,
+    /// CPUs the workers are pinned to in turn, workers are not pinned if empty
+    pub worker_cpus: Vec<usize>,
+    /// Give every worker its own queue of connections, see `ThreadPoolBuilder::work_stealing`
+    pub work_stealing: bool,
+    /// How long in-flight requests are given to finish when the server shuts down
+    pub drain_timeout: Duration,
+    /// Clients, such as reverse proxies, whose X-Request-ID header is used instead of a new ID
+    /// Clients connected through a unix socket are always trusted
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+impl Default for ConnectionSettings {
+    fn default() -> Self {
+        Self {
+            request_read_timeout: Duration::from_secs(5),
+            header_read_timeout: Duration::from_secs(10),
+            keep_alive_timeout: Duration::from_secs(5),
+            max_requests: 100,
+            max_request_line: 8 * 1024,
+            max_header_size: 16 * 1024,
+            max_headers: 100,
+            max_queued: 1024,
+            max_workers: None,
+            worker_idle_timeout: Duration::from_secs(60),
+            worker_stack_size: None,
+            worker_cpus: Vec::new(),
+            work_stealing: false,
+            drain_timeout: Duration::from_secs(30),
+            trusted_proxies: Vec::new(),
+        }
+    }
+}
+
+/// A client connection.
+///
+/// The buffered reader is kept with the connection so that bytes read past the end of a request
+/// (pipelined requests) are not lost when the connection goes back to the event loop.
+pub(crate) struct Connection {
+    reader: BufReader<Stream>,
+    /// Number of requests served so far
+    served: usize,
+    _open: OpenConnection,
+    /// `None` if the client's connections are not limited
+    _client: Option<ClientConnection>,
+}
+
+impl Connection {
+    pub fn new(stream: Stream, metrics: &Arc<Metrics>, client: Option<ClientConnection>) -> Self {
+        Self {
+            reader: BufReader::new(stream),
+            served: 0,
+            _open: Metrics::connection_opened(metrics),
+            _client: client,
+        }
+    }
+
+    pub fn stream(&self) -> &Stream {
+        self.reader.get_ref()
+    }
+
+    /// Serve requests until the client stops sending them.
+    ///
+    /// This is called once the connection is readable, so it does not wait on idle clients.
+    /// If the connection should persist, it is handed back to the event loop once there is no
+    /// more buffered request data.
+    pub fn serve(mut self, state: &ServerState) -> Result<(), HttpServerError> {
+        loop {
+            match self.handle_request(state) {
+                Ok(true) => {}
+                Ok(false) => return Ok(()),
+                Err(ref err) if err.is_limit() => {
+                    refuse(self.stream(), err, &state.metrics);
+                    return Ok(());
+                }
+                Err(err) => {
+                    state.metrics.error(&err);
+                    if err.response_status().is_some() {
+                        // The client may already be gone, the original error is the one worth reporting
+                        let _ = respond_error(self.stream(), &err);
+                    }
+                    return Err(err);
+                }
+            }
+
+            if self.reader.buffer().is_empty() {
+                state.idle.park(self);
+                return Ok(());
+            }
+        }
+    }
+
+    /// Parses a single request and routes it
+    /// Returns whether the connection should persist
+    fn handle_request(&mut self, state: &ServerState) -> Result<bool, HttpServerError> {
+        let settings = &state.settings;
+        let deadline = Instant::now() + settings.header_read_timeout;
+
+        // First line of a request, normally in the format "GET / HTTP/1.1"
+        let mut request_line = String::new();
+
+        // The client closed the connection
+        let mut received = self.read_line(
+            &mut request_line,
+            settings.max_request_line,
+            HttpServerError::RequestLineTooLong,
+            deadline,
+            settings.request_read_timeout,
+        )?;
+        if received == 0 {
+            return Ok(false);
+        }
+        let started = Instant::now();
+        let time = Local::now();
+
+        let mut parts = request_line.split_whitespace();
+        let method = parts.next().ok_or(HttpServerError::HttpMethodNotPresent)?;
+        let request_type = RequestType::try_from(method)
+            .map_err(|_| HttpServerError::UnknownMethod(method.to_string()))?;
+        let path = parts.next().ok_or(HttpServerError::PathNotPresent)?;
+        let version = parts.next().ok_or(HttpServerError::HttpVersionNotPresent)?;
+        let version =
+            HttpVersion::try_from(version).map_err(|_| HttpServerError::InvalidHttpVersion)?;
+
+        // Responses still say HTTP/1.1 to HTTP/1.0 clients, see `ResponseWriter`
+        if version.major != 1 {
+            return Err(HttpServerError::UnsupportedHttpVersion(version));
+        }
+
+        // Older clients may not say which host they want, so it defaults to the address they connected to
+        let mut request = RequestBuilder::new(
+            request_type,
+            &self
+                .stream()
+                .local_addr()
+                .map(|addr| addr.ip().to_string())
+                .unwrap_or_else(|| "localhost".to_string()),
+        );
+        request.path(path).version(version);
+
+        let mut host = None;
+        let mut close = false;
+        let mut keep_alive = false;
+
+        // Parse all the headers
+        let mut line = String::new();
+        let mut header_size = 0;
+        let mut header_count = 0;
+        loop {
+            let read = self.read_line(
+                &mut line,
+                settings.max_header_size - header_size,
+                HttpServerError::HeadersTooLarge,
+                deadline,
+                settings.request_read_timeout,
+            )?;
+            received += read;
+            header_size += read;
+
+            if line.trim().is_empty() {
+                break;
+            }
+
+            header_count += 1;
+            if header_count > settings.max_headers {
+                return Err(HttpServerError::TooManyHeaders);
+            }
+
+            if let Some(header_split_index) = line.find(':') {
+                let (name, value) = line.split_at(header_split_index);
+                let name = name.trim();
+                let value = value[1..].trim();
+
+                if name.eq_ignore_ascii_case("host") {
+                    let normalized = normalize_host(value);
+                    request.host(&normalized);
+                    host = Some(normalized);
+                } else if name.eq_ignore_ascii_case("connection") {
+                    // Connection holds a list of options, e.g. "keep-alive, Upgrade"
+                    for option in value.split(',') {
+                        let option = option.trim();
+                        close |= option.eq_ignore_ascii_case("close");
+                        keep_alive |= option.eq_ignore_ascii_case("keep-alive");
+                    }
+                }
+
+                request.header(name, value);
+            }
+
+            // We reuse the line buffer, so we need to clear it every time
+            line.clear();
+        }
+
+        // HTTP/1.1 made the Host header mandatory
+        if version >= HttpVersion::HTTP_1_1 && host.is_none() {
+            return Err(HttpServerError::HostNotPresent);
+        }
+
+        if let Some(addr) = self.stream().peer_addr() {
+            state.clients.request(addr.ip()).map_err(|wait| {
+                // Retry-After is in whole seconds
+                let seconds = wait.as_secs() + if wait.subsec_nanos() > 0 { 1 } else { 0 };
+                HttpServerError::RateLimited(seconds)
+            })?;
+        }
+
+        let persist = !close && (keep_alive || version.persists_by_default());
+
+        let request = request.build();
+        state.metrics.received(received);
+
+        let framing = Framing::of(request.headers(), Framing::Length(0))?;
+        let expects_continue = version >= HttpVersion::HTTP_1_1
+            && request
+                .headers()
+                .get("expect")
+                .map_or(false, |expect| expect.eq_ignore_ascii_case("100-continue"));
+
+        let request_id = match request.headers().get(REQUEST_ID_HEADER) {
+            Some(id) if self.is_trusted(&state.settings) && request_id::is_valid(id) => {
+                id.to_string()
+            }
+            _ => state.request_ids.generate(),
+        };
+        // Log records emitted while handling the request get its ID
+        let _current_request = CurrentRequest::enter(&request_id);
+
+        // Kept until the request is done, even if the hosts are reloaded in the meantime
+        let hosts = state.hosts.current();
+        let builtin = state.builtin_route(path);
+        let host = match builtin {
+            Some(_) => None,
+            None => Some(
+                hosts
+                    .lookup(request.host())
+                    .ok_or_else(|| HttpServerError::UnknownHost(request.host().to_string()))?,
+            ),
+        };
+
+        self.served += 1;
+        let remaining = state.settings.max_requests.saturating_sub(self.served);
+
+        let mut writer = ResponseWriter::new(
+            self.stream().try_clone()?,
+            KeepAlive {
+                timeout: state.settings.keep_alive_timeout,
+                remaining,
+            },
+            !persist || remaining == 0,
+            state.draining.clone(),
+        );
+        writer.set_request_id(&request_id);
+        if let RequestType::HEAD = request_type {
+            writer.set_head_only();
+        }
+        let progress = writer.progress();
+
+        // The request is moved to the endpoint, the headers the access log needs are kept beforehand
+        let logged_headers = state.access_log.as_ref().map(|_| {
+            let header = |name| request.headers().get(name).map(str::to_string);
+            (header("referer"), header("user-agent"))
+        });
+
+        // The body is read from the connection's reader, which is taken back once the request is done
+        let (body, body_slot) = if framing == Framing::Length(0) {
+            (RequestBody::empty(), None)
+        } else {
+            let placeholder = BufReader::new(self.stream().try_clone()?);
+            let reader = mem::replace(&mut self.reader, placeholder);
+            let (body, slot) = RequestBody::new(
+                reader,
+                framing,
+                state.settings.request_read_timeout,
+                expects_continue,
+            );
+            (body, Some(slot))
+        };
+
+        let mut route_info = HttpRouteInfo {
+            writer,
+            request_id: request_id.clone(),
+            request,
+            body,
+            client_ip: self
+                .stream()
+                .peer_addr()
+                .map(|addr| canonical_ip(addr.ip())),
+            trusted_client: self.is_trusted(&state.settings),
+            workers: state.workers(),
+        };
+        // Middleware may rewrite the path, the endpoint is found with the path they leave
+        let mut routed_path = path.to_string();
+
+        // Endpoints and middleware panicking only fail their own request
+        let handled = {
+            let _in_flight = state.metrics.request_started();
+
+            panics::catch(|| match (builtin, host) {
+                (Some(builtin), _) => state.respond_builtin(builtin, route_info.writer()),
+                (None, Some(host)) => {
+                    let mut passed = Vec::new();
+                    let mut short_circuit = None;
+                    for middleware in state
+                        .middleware
+                        .matching(path)
+                        .chain(host.middleware.matching(path))
+                    {
+                        match middleware.before(&mut route_info) {
+                            Flow::Continue => passed.push(middleware.clone()),
+                            Flow::Respond(response) => {
+                                short_circuit = Some(response);
+                                break;
+                            }
+                        }
+                    }
+
+                    routed_path = route_info.request.path().to_string();
+                    if !passed.is_empty() {
+                        route_info.writer.set_filters(ResponseFilters {
+                            request: route_info.request.clone(),
+                            middleware: passed,
+                        });
+                    }
+
+                    match short_circuit {
+                        Some(response) => route_info
+                            .writer()
+                            .respond(&response.head_bytes(), response.body())?,
+                        None => {
+                            let _ = host.router.route(routed_path.as_str(), route_info);
+                        }
+                    }
+                    Ok(())
+                }
+                (None, None) => Ok(()),
+            })
+        };
+
+        let route = match (builtin, host) {
+            (Some(_), _) => Some(path.to_string()),
+            (None, Some(host)) => host.router.route_name(routed_path.as_str()),
+            (None, None) => None,
+        };
+
+        let body_skipped = match body_slot {
+            Some(slot) => {
+                let source = slot
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .take()
+                    .expect("the body is only taken back once");
+                let (reader, skipped) = source.finish(MAX_SKIPPED_BODY);
+                self.reader = reader;
+                skipped
+            }
+            None => true,
+        };
+
+        let mut status = progress.status();
+        let keep_open = match handled {
+            Ok(result) => {
+                result?;
+                !progress.closes_connection() && body_skipped
+            }
+            Err(panic) => {
+                let err = HttpServerError::EndpointPanicked;
+                let count = state.metrics.error(&err);
+                let answered = progress.bytes_sent() == 0;
+                error!(
+                    path,
+                    panic = panic.message.as_str(),
+                    location = panic.location.as_str(),
+                    answered,
+                    count,
+                    backtrace:? = panic.backtrace;
+                    "Endpoint panicked"
+                );
+
+                // Part of the response may have been sent, in which case it can only be cut short
+                if answered {
+                    let _ = respond_error(self.stream(), &err);
+                    status = Some(500);
+                }
+                false
+            }
+        };
+
+        state.metrics.sent(progress.bytes_sent());
+        state.metrics.request_done(
+            route.as_ref().map_or("", String::as_str),
+            &request_type.to_string(),
+            status,
+            started.elapsed(),
+        );
+
+        if let (Some(access_log), Some((referer, user_agent))) = (&state.access_log, logged_headers)
+        {
+            access_log.log(&AccessEntry {
+                remote: self.stream().peer_addr(),
+                time,
+                request_id: &request_id,
+                method,
+                path,
+                version: &version.to_string(),
+                status,
+                bytes: progress.bytes_sent(),
+                referer: referer.as_deref(),
+                user_agent: user_agent.as_deref(),
+                duration: started.elapsed(),
+            });
+        }
+
+        Ok(keep_open)
+    }
+
+    /// Read a line, line ending included, of at most `limit` bytes
+    ///
+    /// Fails with `too_long` if the line is longer, or with `RequestTimeout` once the client
+    /// reached `deadline` or sent nothing for `read_timeout`.
+    /// Returns the number of bytes read, 0 if the client closed the connection.
+    fn read_line(
+        &mut self,
+        line: &mut String,
+        limit: usize,
+        too_long: HttpServerError,
+        deadline: Instant,
+        read_timeout: Duration,
+    ) -> Result<usize, HttpServerError> {
+        let mut bytes = Vec::new();
+
+        loop {
+            if self.reader.buffer().is_empty() {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(HttpServerError::RequestTimeout);
+                }
+                self.stream()
+                    .set_read_timeout(Some(read_timeout.min(deadline - now)))?;
+            }
+
+            let available = match self.reader.fill_buf() {
+                Ok(available) => available,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(ref err)
+                    if err.kind() == io::ErrorKind::WouldBlock
+                        || err.kind() == io::ErrorKind::TimedOut =>
+                {
+                    return Err(HttpServerError::RequestTimeout)
+                }
+                Err(err) => return Err(err.into()),
+            };
+
+            // The client closed the connection
+            if available.is_empty() {
+                break;
+            }
+
+            let (used, done) = match available.iter().position(|byte| *byte == b'\n') {
+                Some(end) => (end + 1, true),
+                None => (available.len(), false),
+            };
+
+            if bytes.len() + used > limit {
+                return Err(too_long);
+            }
+
+            bytes.extend_from_slice(&available[..used]);
+            self.reader.consume(used);
+
+            if done {
+                break;
+            }
+        }
+
+        let read = bytes.len();
+        let text = String::from_utf8(bytes).map_err(|_| HttpServerError::InvalidUtf8)?;
+        line.push_str(&text);
+
+        Ok(read)
+    }
+
+    /// Whether the client's X-Request-ID header can be used
+    fn is_trusted(&self, settings: &ConnectionSettings) -> bool {
+        match self.stream().peer_addr() {
+            Some(addr) => settings.trusted_proxies.contains(&canonical_ip(addr.ip())),
+            None => true,
+        }
+    }
+}
+
+/// Refuse a client that went over a limit: count it, log it and respond with the error
+pub(crate) fn refuse(stream: &Stream, err: &HttpServerError, metrics: &Metrics) {
+    let count = metrics.error(err);
+    let client = stream
+        .peer_addr()
+        .map_or_else(|| "-".to_string(), |addr| addr.ip().to_string());
+    warn!(limit = err.name(), client = client.as_str(), count; "Request refused by a limit");
+
+    // The client may already be gone
+    let _ = respond_error(stream, err);
+}
+
+/// Respond to a request that could not be handled, the connection is closed afterwards
+fn respond_error(stream: &Stream, err: &HttpServerError) -> Result<(), HttpServerError> {
+    let status = match err.response_status() {
+        Some(status) => status,
+        None => return Ok(()),
+    };
+
+    let mut response = ResponseBuilder::ok_200();
+    response
+        .code(status)
+        .header("Content-Type", "text/plain; charset=UTF-8");
+    if let Some(retry_after) = err.retry_after() {
+        response.header("Retry-After", &retry_after.to_string());
+    }
+    response.body(status.as_bytes().to_vec());
+    let response = response.build();
+
+    let mut writer = ResponseWriter::new(
+        stream.try_clone()?,
+        KeepAlive {
+            timeout: Duration::from_secs(0),
+            remaining: 0,
+        },
+        true,
+        Arc::new(AtomicBool::new(false)),
+    );
+    writer.respond(&response.head_bytes(), response.body())?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Middleware, VirtualHosts};
+    use router::{Endpoint, RoutedInfo};
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+
+    /// Responds with its name
+    struct Named(&'static str);
+
+    impl Endpoint<HttpRouteInfo, ()> for Named {
+        fn process(&self, info: RoutedInfo<HttpRouteInfo>) {
+            let mut info = info.data;
+            let _ = info
+                .writer()
+                .respond(b"HTTP/1.1 200 OK\r\n", self.0.as_bytes());
+        }
+    }
+
+    /// Sends requests for "/old" to "/new"
+    struct Rewrite;
+
+    impl Middleware for Rewrite {
+        fn before(&self, info: &mut HttpRouteInfo) -> Flow {
+            if info.request().path() == "/old" {
+                info.request_mut().set_path("/new");
+            }
+            Flow::Continue
+        }
+    }
+
+    /// Response to `request`, sent on a connection served until the client is done
+    /// Requests that fail are answered with their error's status
+    fn serve(state: &ServerState, request: &[u8]) -> String {
+        let (server, mut client) = UnixStream::pair().unwrap();
+        client.write_all(request).unwrap();
+        let connection = Connection::new(Stream::Unix(server), &state.metrics, None);
+        let _ = connection.serve(state);
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    /// State of a server with a "hello" endpoint at "/" and `settings`
+    fn with_settings(settings: ConnectionSettings) -> ServerState {
+        let mut hosts = VirtualHosts::default();
+        hosts.default_mut().add_path("/", Named("hello"));
+        ServerState {
+            settings,
+            ..ServerState::for_tests(hosts)
+        }
+    }
+
+    fn status_line(response: &str) -> &str {
+        response.split("\r\n").next().unwrap()
+    }
+
+    #[test]
+    fn route_rewritten_path() {
+        let mut hosts = VirtualHosts::default();
+        hosts.default_mut().add_path("/old", Named("old"));
+        hosts.default_mut().add_path("/new", Named("new"));
+        hosts.default_middleware_mut().add(Rewrite);
+        let state = ServerState::for_tests(hosts);
+
+        let response = serve(
+            &state,
+            b"GET /old HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nnew"));
+    }
+
+    #[test]
+    fn respond_to_http_1_0() {
+        let mut hosts = VirtualHosts::default();
+        hosts.default_mut().add_path("/", Named("hello"));
+        let state = ServerState::for_tests(hosts);
+
+        // Persistence is up to the client, the connection is closed unless it asks otherwise
+        let response = serve(&state, b"GET / HTTP/1.0\r\n\r\n");
+        let lines = response
+            .split("\r\n")
+            .filter(|line| !line.starts_with("X-Request-ID:"));
+        assert_eq!(
+            lines.collect::<Vec<_>>(),
+            [
+                "HTTP/1.1 200 OK",
+                "Content-Length: 5",
+                "Connection: close",
+                "",
+                "hello"
+            ]
+        );
+
+        // The second request does not ask to be kept alive, so the connection is closed after it
+        let response = serve(
+            &state,
+            b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\nGET / HTTP/1.0\r\n\r\n",
+        );
+        let lines = response
+            .split("\r\n")
+            .filter(|line| !line.starts_with("X-Request-ID:"));
+        assert_eq!(
+            lines.collect::<Vec<_>>(),
+            [
+                "HTTP/1.1 200 OK",
+                "Content-Length: 5",
+                "Connection: keep-alive",
+                "Keep-Alive: timeout=5, max=99",
+                "",
+                "helloHTTP/1.1 200 OK",
+                "Content-Length: 5",
+                "Connection: close",
+                "",
+                "hello",
+            ]
+        );
+    }
+
+    #[test]
+    fn refuse_large_heads() {
+        let state = with_settings(ConnectionSettings {
+            max_request_line: 32,
+            max_header_size: 64,
+            max_headers: 2,
+            ..ConnectionSettings::default()
+        });
+        let long = "a".repeat(64);
+
+        let response = serve(
+            &state,
+            b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        );
+        assert_eq!(status_line(&response), "HTTP/1.1 200 OK");
+
+        let request = format!("GET /{} HTTP/1.1\r\nHost: localhost\r\n\r\n", long);
+        let response = serve(&state, request.as_bytes());
+        assert_eq!(status_line(&response), "HTTP/1.1 414 URI Too Long");
+
+        let request = format!(
+            "GET / HTTP/1.1\r\nHost: localhost\r\nX-Long: {}\r\n\r\n",
+            long
+        );
+        let response = serve(&state, request.as_bytes());
+        assert_eq!(
+            status_line(&response),
+            "HTTP/1.1 431 Request Header Fields Too Large"
+        );
+
+        let response = serve(
+            &state,
+            b"GET / HTTP/1.1\r\nHost: localhost\r\nA: 1\r\nB: 2\r\n\r\n",
+        );
+        assert_eq!(
+            status_line(&response),
+            "HTTP/1.1 431 Request Header Fields Too Large"
+        );
+    }
+
+    #[test]
+    fn refuse_trickled_head() {
+        let state = with_settings(ConnectionSettings {
+            header_read_timeout: Duration::from_millis(200),
+            ..ConnectionSettings::default()
+        });
+        let (server, mut client) = UnixStream::pair().unwrap();
+
+        // Every header comes well within the read timeout, but the head never ends
+        let mut trickle = client.try_clone().unwrap();
+        let trickling = thread::spawn(move || {
+            trickle.write_all(b"GET / HTTP/1.1\r\n")?;
+            for _ in 0..50 {
+                thread::sleep(Duration::from_millis(20));
+                trickle.write_all(b"X-Trickle: 1\r\n")?;
+            }
+            Ok::<_, io::Error>(())
+        });
+
+        let started = Instant::now();
+        let connection = Connection::new(Stream::Unix(server), &state.metrics, None);
+        connection.serve(&state).unwrap();
+        assert!(started.elapsed() < Duration::from_millis(900));
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert_eq!(status_line(&response), "HTTP/1.1 408 Request Timeout");
+        assert!(trickling.join().unwrap().is_err());
+    }
+
+    #[test]
+    fn refuse_invalid_utf8() {
+        let state = with_settings(ConnectionSettings::default());
+
+        let response = serve(&state, b"GET /\xff HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert_eq!(status_line(&response), "HTTP/1.1 400 Bad Request");
+
+        let response = serve(&state, b"GET / HTTP/1.1\r\nHost: local\xc3host\r\n\r\n");
+        assert_eq!(status_line(&response), "HTTP/1.1 400 Bad Request");
+    }
+}
@@ -0,0 +1,588 @@
+use crate::client_limits::ClientTracker;
+use crate::connection::{self, Connection};
+use crate::handoff;
+use crate::listener::Listener;
+use crate::reload::Reloader;
+use crate::{HttpServerError, ReloadHook, ServerState};
+use crossbeam::channel;
+use mio::unix::EventedFd;
+use mio::{Events, Poll, PollOpt, Ready, Registration, SetReadiness, Token};
+use signal_hook::iterator::Signals;
+use signal_hook::{SIGHUP, SIGINT, SIGTERM, SIGUSR2};
+use std::collections::HashMap;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::panic::RefUnwindSafe;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+const WAKER: Token = Token(0);
+const SIGNALS: Token = Token(1);
+/// Listeners get the tokens following this one, connections the ones after the listeners
+const FIRST_LISTENER: usize = 2;
+
+/// How often in-flight requests are checked while draining
+const DRAIN_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long a listener stops accepting connections after it failed to, e.g. because the process
+/// ran out of file descriptors
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Accept failures are logged at most once per interval
+const ACCEPT_WARNING_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Used by workers to hand idle keep-alive connections back to the event loop
+pub(crate) struct IdleSender {
+    sender: channel::Sender<Connection>,
+    waker: SetReadiness,
+}
+
+// `SetReadiness` only updates an atomic readiness, a panic can't leave it in a broken state
+impl RefUnwindSafe for IdleSender {}
+
+impl IdleSender {
+    pub fn park(&self, connection: Connection) {
+        self.sender.send(connection);
+        let _ = self.waker.set_readiness(Ready::readable());
+    }
+}
+
+/// A connection waiting for its client to send something
+struct IdleConnection {
+    connection: Connection,
+    since: Instant,
+}
+
+/// Counts the failures to accept a connection, so that they are logged at most once per
+/// `ACCEPT_WARNING_INTERVAL`
+#[derive(Default)]
+struct AcceptFailures {
+    /// Failures since the last one that was logged
+    unlogged: usize,
+    logged_at: Option<Instant>,
+}
+
+impl AcceptFailures {
+    fn failed(&mut self, err: &io::Error) {
+        self.unlogged += 1;
+
+        let now = Instant::now();
+        if self
+            .logged_at
+            .map_or(true, |logged_at| now - logged_at >= ACCEPT_WARNING_INTERVAL)
+        {
+            warn!(
+                error:% = err,
+                failures = self.unlogged;
+                "Could not accept connections, pausing the listener"
+            );
+            self.unlogged = 0;
+            self.logged_at = Some(now);
+        }
+    }
+}
+
+/// Waits on the listeners and on idle connections using epoll.
+///
+/// Connections are only given to a worker once they are readable, so idle clients don't hold
+/// a worker thread. Workers give persistent connections back through an `IdleSender`.
+///
+/// On SIGTERM or SIGINT, the loop stops accepting connections, closes idle ones and returns once
+/// in-flight requests are done or the drain timeout is reached.
+/// On SIGUSR2, a new server process is started with the listening sockets before draining,
+/// so a new binary can take over without ever closing the port.
+/// On SIGHUP, the virtual hosts are rebuilt with the reload hook, on a thread of its own.
+pub(crate) struct EventLoop {
+    poll: Poll,
+    /// Empty once the server is draining
+    listeners: Vec<Listener>,
+    /// Listeners that stopped accepting after a failure, by index, and when they start again
+    paused: Vec<(usize, Instant)>,
+    accept_failures: AcceptFailures,
+    signals: Signals,
+    idle_receiver: channel::Receiver<Connection>,
+    // Kept alive for the waker to stay registered
+    _registration: Registration,
+    waker: SetReadiness,
+    connections: HashMap<Token, IdleConnection>,
+    next_token: usize,
+    /// Tokens before this one are reserved
+    first_connection: usize,
+    /// How long an idle connection is kept open
+    idle_timeout: Duration,
+    /// Moved to `reloader` once the loop runs
+    reload: Option<ReloadHook>,
+    reloader: Option<Reloader>,
+}
+
+impl EventLoop {
+    pub fn new(
+        listeners: Vec<Listener>,
+        idle_timeout: Duration,
+        reload: Option<ReloadHook>,
+    ) -> Result<(Self, IdleSender), HttpServerError> {
+        let poll = Poll::new()?;
+
+        for (index, listener) in listeners.iter().enumerate() {
+            listener.set_nonblocking(true)?;
+            poll.register(
+                &EventedFd(&listener.as_raw_fd()),
+                Token(FIRST_LISTENER + index),
+                Ready::readable(),
+                PollOpt::level(),
+            )?;
+        }
+        let first_connection = FIRST_LISTENER + listeners.len();
+
+        let signals = Signals::new(&[SIGTERM, SIGINT, SIGUSR2, SIGHUP])?;
+        poll.register(&signals, SIGNALS, Ready::readable(), PollOpt::level())?;
+
+        let (registration, waker) = Registration::new2();
+        poll.register(&registration, WAKER, Ready::readable(), PollOpt::level())?;
+
+        let (sender, idle_receiver) = channel::unbounded();
+
+        Ok((
+            Self {
+                poll,
+                listeners,
+                paused: Vec::new(),
+                accept_failures: AcceptFailures::default(),
+                signals,
+                idle_receiver,
+                _registration: registration,
+                waker: waker.clone(),
+                connections: HashMap::new(),
+                next_token: first_connection,
+                first_connection,
+                idle_timeout,
+                reload,
+                reloader: None,
+            },
+            IdleSender { sender, waker },
+        ))
+    }
+
+    /// Run the event loop, `dispatch` is called with every connection that has data to read
+    /// Returns once the server is done draining
+    pub fn run(
+        mut self,
+        state: &ServerState,
+        mut dispatch: impl FnMut(Connection),
+    ) -> Result<(), HttpServerError> {
+        if let Some(hook) = self.reload.take() {
+            let reloader = Reloader::spawn(hook, state.hosts.clone(), state.reload_failed.clone())?;
+            self.reloader = Some(reloader);
+        }
+
+        let mut events = Events::with_capacity(1024);
+        let mut drain_deadline = None;
+        state
+            .listening
+            .store(self.listeners.len(), Ordering::SeqCst);
+
+        loop {
+            let timeout = match drain_deadline {
+                Some(_) => DRAIN_CHECK_INTERVAL,
+                None => self.idle_timeout,
+            };
+            let shutdown = self.turn(&mut events, timeout, state, &mut dispatch)?;
+
+            if shutdown && drain_deadline.is_none() {
+                info!("Shutting down, draining in-flight requests");
+                self.start_draining(state)?;
+                drain_deadline = Some(Instant::now() + state.settings.drain_timeout);
+            }
+
+            self.resume_listeners()?;
+            self.close_expired();
+            state.clients.prune();
+
+            if let Some(deadline) = drain_deadline {
+                let in_flight = state.in_flight.load(Ordering::SeqCst);
+
+                if in_flight == 0 {
+                    return Ok(());
+                }
+
+                if Instant::now() >= deadline {
+                    warn!(in_flight; "Drain timeout reached with requests still in flight");
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Wait up to `timeout` for events and handle them
+    /// Returns whether a signal asked the server to shut down
+    fn turn(
+        &mut self,
+        events: &mut Events,
+        mut timeout: Duration,
+        state: &ServerState,
+        dispatch: &mut impl FnMut(Connection),
+    ) -> Result<bool, HttpServerError> {
+        if !self.paused.is_empty() {
+            timeout = timeout.min(ACCEPT_BACKOFF);
+        }
+        self.poll.poll(events, Some(timeout))?;
+
+        let mut shutdown = false;
+        for event in events.iter() {
+            match event.token() {
+                Token(index) if index >= FIRST_LISTENER && index < self.first_connection => {
+                    self.accept(index - FIRST_LISTENER, state)?
+                }
+                WAKER => self.receive_idle(event.readiness(), state)?,
+                SIGNALS => {
+                    let signals: Vec<_> = self.signals.pending().collect();
+
+                    for signal in signals {
+                        match signal {
+                            SIGUSR2 => shutdown |= self.upgrade(),
+                            SIGHUP => self.reload(),
+                            _ => shutdown = true,
+                        }
+                    }
+                }
+                token => {
+                    if let Some(idle) = self.connections.remove(&token) {
+                        let stream = idle.connection.stream();
+                        // Only this connection is dropped, the others are still served
+                        if let Err(err) = self.poll.deregister(&EventedFd(&stream.as_raw_fd())) {
+                            warn!(error:% = err; "Could not stop polling a connection, closing it");
+                            continue;
+                        }
+                        dispatch(idle.connection);
+                    }
+                }
+            }
+        }
+
+        Ok(shutdown)
+    }
+
+    /// Start a new server process that takes over the listening socket
+    /// Returns whether this server should shut down
+    fn upgrade(&self) -> bool {
+        if self.listeners.is_empty() {
+            return false;
+        }
+
+        match handoff::spawn_upgrade(&self.listeners) {
+            Ok(child) => {
+                info!(pid = child.id(); "Started upgraded server");
+                true
+            }
+            Err(err) => {
+                error!(error:% = err; "Could not start upgraded server");
+                false
+            }
+        }
+    }
+
+    /// Have the reloader rebuild the virtual hosts, without waiting for it
+    fn reload(&self) {
+        match &self.reloader {
+            Some(reloader) => reloader.request(),
+            None => warn!("Received SIGHUP but the server has no reload hook"),
+        }
+    }
+
+    /// Stop accepting connections and close the idle ones
+    /// Responses sent from now on close their connection
+    fn start_draining(&mut self, state: &ServerState) -> Result<(), HttpServerError> {
+        state.draining.store(true, Ordering::SeqCst);
+        state.listening.store(0, Ordering::SeqCst);
+
+        for (index, listener) in self.listeners.drain(..).enumerate() {
+            // Paused listeners are not registered
+            if self.paused.iter().all(|(paused, _)| *paused != index) {
+                self.poll.deregister(&EventedFd(&listener.as_raw_fd()))?;
+            }
+        }
+        self.paused.clear();
+
+        // The connections are closed once dropped, whether they are still polled doesn't matter
+        for (_, idle) in self.connections.drain() {
+            let _ = self
+                .poll
+                .deregister(&EventedFd(&idle.connection.stream().as_raw_fd()));
+        }
+
+        Ok(())
+    }
+
+    /// Accept every pending connection of a listener
+    fn accept(&mut self, listener: usize, state: &ServerState) -> Result<(), HttpServerError> {
+        loop {
+            let accepted = match self.listeners.get(listener) {
+                Some(listener) => listener.accept(),
+                None => return Ok(()),
+            };
+
+            match accepted {
+                Ok(stream) => {
+                    // Accepted sockets can inherit the listener's non-blocking mode on some platforms
+                    // A connection that can't be served is dropped, without failing the listener
+                    if let Err(err) = stream.set_nonblocking(false) {
+                        warn!(error:% = err; "Could not set up an accepted connection, closing it");
+                        continue;
+                    }
+
+                    let client = match stream.peer_addr() {
+                        Some(addr) => ClientTracker::connection_opened(&state.clients, addr.ip()),
+                        None => Ok(None),
+                    };
+                    match client {
+                        Ok(client) => self.park(Connection::new(stream, &state.metrics, client)),
+                        // The request is not read, so the client may see a reset instead
+                        Err(()) => connection::refuse(
+                            &stream,
+                            &HttpServerError::TooManyConnections,
+                            &state.metrics,
+                        ),
+                    }
+                }
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                // Only this connection failed, e.g. the client reset it before it was accepted
+                Err(ref err)
+                    if err.kind() == io::ErrorKind::ConnectionAborted
+                        || err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => {
+                    // Such as running out of file descriptors, the listener stays readable
+                    // meanwhile, so it is paused rather than polled in a loop
+                    self.accept_failures.failed(&err);
+                    self.pause(listener)?;
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Stop polling a listener for `ACCEPT_BACKOFF`
+    fn pause(&mut self, index: usize) -> Result<(), HttpServerError> {
+        self.poll
+            .deregister(&EventedFd(&self.listeners[index].as_raw_fd()))?;
+        self.paused.push((index, Instant::now() + ACCEPT_BACKOFF));
+        Ok(())
+    }
+
+    /// Poll the paused listeners again once their backoff is over
+    fn resume_listeners(&mut self) -> Result<(), HttpServerError> {
+        let now = Instant::now();
+
+        for (index, _) in self.paused.iter().filter(|(_, until)| *until <= now) {
+            self.poll.register(
+                &EventedFd(&self.listeners[*index].as_raw_fd()),
+                Token(FIRST_LISTENER + index),
+                Ready::readable(),
+                PollOpt::level(),
+            )?;
+        }
+        self.paused.retain(|(_, until)| *until > now);
+
+        Ok(())
+    }
+
+    /// Park the connections workers are done with
+    /// While draining they are closed instead
+    fn receive_idle(
+        &mut self,
+        readiness: Ready,
+        state: &ServerState,
+    ) -> Result<(), HttpServerError> {
+        if readiness.is_readable() {
+            // Readiness is reset before receiving so a connection sent in between wakes us up again
+            self.waker.set_readiness(Ready::empty())?;
+
+            while let Some(connection) = self.idle_receiver.try_recv() {
+                if !state.draining.load(Ordering::SeqCst) {
+                    self.park(connection);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Wait for the connection to be readable without holding a worker
+    /// The connection is closed if it can't be polled, e.g. once epoll reached `max_user_watches`
+    fn park(&mut self, connection: Connection) {
+        let token = Token(self.next_token);
+        self.next_token += 1;
+
+        if let Err(err) = self.poll.register(
+            &EventedFd(&connection.stream().as_raw_fd()),
+            token,
+            Ready::readable(),
+            PollOpt::level(),
+        ) {
+            warn!(error:% = err; "Could not poll an idle connection, closing it");
+            return;
+        }
+
+        self.connections.insert(
+            token,
+            IdleConnection {
+                connection,
+                since: Instant::now(),
+            },
+        );
+    }
+
+    /// Close connections that have been idle for too long
+    fn close_expired(&mut self) {
+        let poll = &self.poll;
+        let idle_timeout = self.idle_timeout;
+
+        self.connections.retain(|_, idle| {
+            let alive = idle.since.elapsed() < idle_timeout;
+            if !alive {
+                let _ = poll.deregister(&EventedFd(&idle.connection.stream().as_raw_fd()));
+            }
+            alive
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::listener::Stream;
+    use crate::{HttpRouteInfo, VirtualHosts};
+    use router::{Endpoint, RoutedInfo};
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+
+    const REQUEST: &[u8] = b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+
+    /// Responds with "hello"
+    struct Hello;
+
+    impl Endpoint<HttpRouteInfo, ()> for Hello {
+        fn process(&self, info: RoutedInfo<HttpRouteInfo>) {
+            let mut info = info.data;
+            let _ = info.writer().respond(b"HTTP/1.1 200 OK\r\n", b"hello");
+        }
+    }
+
+    /// Event loop without listeners, and the state of a server parking connections in it
+    fn event_loop(idle_timeout: Duration) -> (EventLoop, ServerState) {
+        let (event_loop, idle) = EventLoop::new(Vec::new(), idle_timeout, None).unwrap();
+        let mut hosts = VirtualHosts::default();
+        hosts.default_mut().add_path("/", Hello);
+
+        let state = ServerState {
+            idle,
+            ..ServerState::for_tests(hosts)
+        };
+        (event_loop, state)
+    }
+
+    /// Handle the pending events, returns the connections that were dispatched
+    fn turn(event_loop: &mut EventLoop, state: &ServerState) -> Vec<Connection> {
+        let mut events = Events::with_capacity(16);
+        let mut dispatched = Vec::new();
+        event_loop
+            .turn(
+                &mut events,
+                Duration::from_millis(100),
+                state,
+                &mut |connection| dispatched.push(connection),
+            ).unwrap();
+        dispatched
+    }
+
+    fn connection(state: &ServerState) -> (Connection, UnixStream) {
+        let (server, client) = UnixStream::pair().unwrap();
+        let connection = Connection::new(Stream::Unix(server), &state.metrics, None);
+        (connection, client)
+    }
+
+    /// Read a response to `REQUEST`
+    fn read_response(client: &mut UnixStream) -> String {
+        let mut response = Vec::new();
+        let mut buffer = [0; 1024];
+        while !response.ends_with(b"hello") {
+            let read = client.read(&mut buffer).unwrap();
+            assert_ne!(read, 0, "the connection was closed");
+            response.extend_from_slice(&buffer[..read]);
+        }
+        String::from_utf8(response).unwrap()
+    }
+
+    #[test]
+    fn idle_connection_holds_no_worker() {
+        let (mut event_loop, state) = event_loop(Duration::from_secs(5));
+        let (connection, mut client) = connection(&state);
+        client.write_all(REQUEST).unwrap();
+
+        // The worker is given back once the request is served, without waiting for the next one
+        connection.serve(&state).unwrap();
+        assert!(read_response(&mut client).starts_with("HTTP/1.1 200 OK\r\n"));
+
+        assert!(turn(&mut event_loop, &state).is_empty());
+        assert_eq!(event_loop.connections.len(), 1);
+    }
+
+    #[test]
+    fn dispatch_readable_connection() {
+        let (mut event_loop, state) = event_loop(Duration::from_secs(5));
+        let (connection, mut client) = connection(&state);
+        event_loop.park(connection);
+
+        assert!(turn(&mut event_loop, &state).is_empty());
+        assert_eq!(event_loop.connections.len(), 1);
+
+        client.write_all(REQUEST).unwrap();
+        let mut dispatched = turn(&mut event_loop, &state);
+        assert_eq!(dispatched.len(), 1);
+        assert!(event_loop.connections.is_empty());
+
+        // Served then parked again
+        dispatched.pop().unwrap().serve(&state).unwrap();
+        assert!(read_response(&mut client).ends_with("\r\n\r\nhello"));
+        assert!(turn(&mut event_loop, &state).is_empty());
+        assert_eq!(event_loop.connections.len(), 1);
+    }
+
+    #[test]
+    fn close_expired_connections() {
+        let (mut event_loop, state) = event_loop(Duration::from_millis(50));
+        let (expired, mut expired_client) = connection(&state);
+        event_loop.park(expired);
+
+        thread::sleep(Duration::from_millis(60));
+        let (fresh, _fresh_client) = connection(&state);
+        event_loop.park(fresh);
+
+        event_loop.close_expired();
+        assert_eq!(event_loop.connections.len(), 1);
+        assert_eq!(expired_client.read(&mut [0; 16]).unwrap(), 0);
+    }
+
+    #[test]
+    fn drain_connections() {
+        let (mut event_loop, state) = event_loop(Duration::from_secs(5));
+        let (parked, mut parked_client) = connection(&state);
+        event_loop.park(parked);
+        let (in_flight, mut in_flight_client) = connection(&state);
+        in_flight_client.write_all(REQUEST).unwrap();
+
+        event_loop.start_draining(&state).unwrap();
+        assert!(event_loop.connections.is_empty());
+        assert_eq!(parked_client.read(&mut [0; 16]).unwrap(), 0);
+
+        // A request served while draining closes its connection instead of parking it
+        in_flight.serve(&state).unwrap();
+        assert!(turn(&mut event_loop, &state).is_empty());
+        assert!(event_loop.connections.is_empty());
+
+        let mut response = String::new();
+        in_flight_client.read_to_string(&mut response).unwrap();
+        assert!(response.contains("\r\nConnection: close\r\n"));
+        assert!(response.ends_with("\r\n\r\nhello"));
+    }
+}
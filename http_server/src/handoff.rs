@@ -0,0 +1,161 @@
+//! Passing listening sockets to a new server process, so that binaries can be upgraded without
+//! closing the port.
+//!
+//! Sockets are passed the systemd way: as file descriptors starting at 3, with their count in
+//! `LISTEN_FDS`. This means the server also accepts sockets from systemd socket activation.
+
+use crate::listener::Listener;
+use std::env;
+use std::io;
+use std::mem;
+use std::net::TcpListener;
+use std::ops::Range;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::UnixListener;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command};
+
+/// First file descriptor passed, the ones before it are stdin, stdout and stderr
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Takes the listening sockets passed by a parent process or systemd
+///
+/// Returns an empty vector if none were passed. `LISTEN_PID` is checked when set, an upgrading
+/// server can't know the pid of the process it spawns so it does not set it.
+/// The variables are removed so that they don't leak to processes spawned by this one.
+pub fn inherited_listeners() -> Vec<Listener> {
+    let fds = passed_fds(
+        env::var("LISTEN_FDS").ok().as_deref(),
+        env::var("LISTEN_PID").ok().as_deref(),
+        std::process::id(),
+    );
+
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDNAMES");
+
+    fds.map(inherit).collect()
+}
+
+/// File descriptors passed to process `pid`, given the values of `LISTEN_FDS` and `LISTEN_PID`
+/// The range is empty if none were passed or if they were meant for another process
+fn passed_fds(count: Option<&str>, listen_pid: Option<&str>, pid: u32) -> Range<RawFd> {
+    let pid = pid.to_string();
+    let for_us = listen_pid.is_none() || listen_pid == Some(pid.as_str());
+    let end = count
+        .and_then(|count| count.parse::<RawFd>().ok())
+        .filter(|count| *count > 0)
+        .and_then(|count| LISTEN_FDS_START.checked_add(count));
+
+    match end {
+        Some(end) if for_us => LISTEN_FDS_START..end,
+        _ => LISTEN_FDS_START..LISTEN_FDS_START,
+    }
+}
+
+/// Take ownership of an inherited socket
+fn inherit(fd: RawFd) -> Listener {
+    let mut addr: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+
+    let is_unix = unsafe {
+        libc::getsockname(fd, &mut addr as *mut _ as *mut libc::sockaddr, &mut len) == 0
+            && libc::c_int::from(addr.ss_family) == libc::AF_UNIX
+    };
+
+    // The descriptors are owned by this process from now on
+    unsafe {
+        if is_unix {
+            Listener::Unix(UnixListener::from_raw_fd(fd))
+        } else {
+            Listener::Tcp(TcpListener::from_raw_fd(fd))
+        }
+    }
+}
+
+/// Start a new instance of the server that inherits `listeners`
+///
+/// The executable is the one this process was started with (`argv[0]`), so a binary replaced on
+/// disk is the one started. It gets the same arguments.
+pub(crate) fn spawn_upgrade(listeners: &[Listener]) -> io::Result<Child> {
+    let mut args = env::args_os();
+    let program = args.next().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            "Could not find the server executable",
+        )
+    })?;
+
+    let fds: Vec<RawFd> = listeners
+        .iter()
+        .map(|listener| listener.as_raw_fd())
+        .collect();
+    let count = fds.len() as RawFd;
+    // Allocated here since allocating between fork and exec is not safe
+    let mut moved = vec![0; fds.len()];
+
+    let mut command = Command::new(program);
+    command
+        .args(args)
+        .env("LISTEN_FDS", count.to_string())
+        .env_remove("LISTEN_PID");
+
+    // Only async-signal-safe calls are allowed between fork and exec
+    unsafe {
+        command.pre_exec(move || {
+            // Sockets are first moved out of the target range so none of them get overwritten.
+            // These copies are closed on exec.
+            for (index, fd) in fds.iter().enumerate() {
+                moved[index] = libc::fcntl(*fd, libc::F_DUPFD_CLOEXEC, LISTEN_FDS_START + count);
+                if moved[index] < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+
+            // Descriptors created by dup2 are kept open on exec
+            for (index, fd) in moved.iter().enumerate() {
+                if libc::dup2(*fd, LISTEN_FDS_START + index as RawFd) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+
+            Ok(())
+        });
+    }
+
+    command.spawn()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_listen_env() {
+        let pid = std::process::id();
+        let other_pid = (pid + 1).to_string();
+
+        assert_eq!(passed_fds(Some("2"), None, pid), 3..5);
+        assert_eq!(passed_fds(Some("1"), Some(&pid.to_string()), pid), 3..4);
+
+        // Sockets passed to another process, e.g. a parent that did not clear the variables
+        assert!(passed_fds(Some("2"), Some(&other_pid), pid)
+            .next()
+            .is_none());
+
+        for count in &[
+            None,
+            Some(""),
+            Some("two"),
+            Some("0"),
+            Some("-1"),
+            Some("2147483647"),
+        ] {
+            assert!(
+                passed_fds(*count, None, pid).next().is_none(),
+                "{:?}",
+                count
+            );
+        }
+    }
+}
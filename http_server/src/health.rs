@@ -0,0 +1,187 @@
+use crate::response_writer::ResponseWriter;
+use crate::ServerState;
+use http::ResponseBuilder;
+use std::fmt::Write as FmtWrite;
+use std::io;
+use std::sync::atomic::Ordering;
+
+/// Built-in endpoints for load balancers and process supervisors
+///
+/// They are served for every host, before any router.
+#[derive(Debug, Clone)]
+pub struct HealthChecks {
+    /// Answers 200 as long as the process serves requests
+    pub liveness_path: String,
+    /// Answers 503 while the server should not get new traffic: while it is draining, once its
+    /// worker queue is saturated or after a reload failed
+    pub readiness_path: String,
+    /// Connections waiting for a worker from which the server is not ready
+    /// `None` uses the `max_queued` of the server's settings, from which connections are refused
+    pub max_queued: Option<usize>,
+}
+
+impl Default for HealthChecks {
+    fn default() -> Self {
+        Self {
+            liveness_path: "/healthz".to_string(),
+            readiness_path: "/readyz".to_string(),
+            max_queued: None,
+        }
+    }
+}
+
+/// Respond to a liveness check
+pub(crate) fn respond_liveness(writer: &mut ResponseWriter) -> io::Result<()> {
+    respond(writer, "200 OK", "ok\n")
+}
+
+/// Respond to a readiness check, the body says which checks failed
+pub(crate) fn respond_readiness(
+    state: &ServerState,
+    checks: &HealthChecks,
+    writer: &mut ResponseWriter,
+) -> io::Result<()> {
+    let draining = state.draining.load(Ordering::SeqCst);
+    let listening = state.listening.load(Ordering::SeqCst);
+    let queued = state.metrics.queued();
+    let reload_failed = state.reload_failed.load(Ordering::SeqCst);
+    let max_queued = checks.max_queued.unwrap_or(state.settings.max_queued);
+
+    let saturated = queued >= max_queued;
+    let ready = !draining && listening > 0 && !saturated && !reload_failed;
+
+    let mut body = String::new();
+    let _ = writeln!(body, "{}", if ready { "ready" } else { "not ready" });
+    let _ = writeln!(body, "draining: {}", draining);
+    let _ = writeln!(body, "listeners: {}", listening);
+    let _ = writeln!(body, "queued: {}/{}", queued, max_queued);
+    let _ = writeln!(body, "reload failed: {}", reload_failed);
+
+    if ready {
+        respond(writer, "200 OK", &body)
+    } else {
+        respond(writer, "503 Service Unavailable", &body)
+    }
+}
+
+fn respond(writer: &mut ResponseWriter, status: &str, body: &str) -> io::Result<()> {
+    let mut response = ResponseBuilder::ok_200();
+    response
+        .code(status)
+        .header("Content-Type", "text/plain; charset=UTF-8")
+        .header("Cache-Control", "no-store")
+        .body(body.as_bytes().to_vec());
+    let response = response.build();
+
+    writer.respond(&response.head_bytes(), response.body())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::response_writer::KeepAlive;
+    use crate::{ConnectionSettings, Stream, VirtualHosts};
+    use crossbeam::channel;
+    use pool::{Task, ThreadPool};
+    use std::io::Read;
+    use std::os::unix::net::UnixStream;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    /// Status line of the readiness check of `state`
+    fn readiness(state: &ServerState, checks: &HealthChecks) -> String {
+        let (server, mut client) = UnixStream::pair().unwrap();
+        let mut writer = ResponseWriter::new(
+            Stream::Unix(server),
+            KeepAlive {
+                timeout: Duration::from_secs(5),
+                remaining: 0,
+            },
+            true,
+            Arc::new(AtomicBool::new(false)),
+        );
+        respond_readiness(state, checks, &mut writer).unwrap();
+        drop(writer);
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        response.split("\r\n").next().unwrap().to_string()
+    }
+
+    #[test]
+    fn ready_unless_failing() {
+        let state = ServerState::for_tests(VirtualHosts::default());
+        let checks = HealthChecks::default();
+        state.listening.store(1, Ordering::SeqCst);
+        assert_eq!(readiness(&state, &checks), "HTTP/1.1 200 OK");
+
+        state.draining.store(true, Ordering::SeqCst);
+        assert_eq!(
+            readiness(&state, &checks),
+            "HTTP/1.1 503 Service Unavailable"
+        );
+        state.draining.store(false, Ordering::SeqCst);
+
+        state.reload_failed.store(true, Ordering::SeqCst);
+        assert_eq!(
+            readiness(&state, &checks),
+            "HTTP/1.1 503 Service Unavailable"
+        );
+        state.reload_failed.store(false, Ordering::SeqCst);
+
+        state.listening.store(0, Ordering::SeqCst);
+        assert_eq!(
+            readiness(&state, &checks),
+            "HTTP/1.1 503 Service Unavailable"
+        );
+    }
+
+    #[test]
+    fn saturated_queue() {
+        let state = ServerState {
+            settings: ConnectionSettings {
+                max_queued: 2,
+                ..ConnectionSettings::default()
+            },
+            ..ServerState::for_tests(VirtualHosts::default())
+        };
+        state.listening.store(1, Ordering::SeqCst);
+
+        // The only worker is kept busy while work is queued behind it
+        let workers: ThreadPool<(), Task<()>> = ThreadPool::new(1, ());
+        state.metrics.watch_pool(workers.stats());
+        let (started, running) = channel::bounded(0);
+        let (release, released) = channel::bounded::<()>(0);
+        let busy = workers.submit(move |_: &()| {
+            started.send(());
+            released.recv();
+        });
+        running.recv().unwrap();
+
+        let queued: Vec<_> = (0..2).map(|_| workers.submit(|_: &()| ())).collect();
+        assert_eq!(state.metrics.queued(), 2);
+
+        // The queue is as full as the server's limit, from which connections are refused
+        let checks = HealthChecks::default();
+        assert_eq!(
+            readiness(&state, &checks),
+            "HTTP/1.1 503 Service Unavailable"
+        );
+        let checks = HealthChecks {
+            max_queued: Some(3),
+            ..HealthChecks::default()
+        };
+        assert_eq!(readiness(&state, &checks), "HTTP/1.1 200 OK");
+
+        drop(release);
+        busy.wait().unwrap();
+        for task in queued {
+            task.wait().unwrap();
+        }
+        assert_eq!(
+            readiness(&state, &HealthChecks::default()),
+            "HTTP/1.1 200 OK"
+        );
+    }
+}
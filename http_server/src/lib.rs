@@ -6,28 +6,202 @@ extern crate failure;
 
 #[macro_use]
 extern crate http;
+#[macro_use]
+extern crate log;
 extern crate pool;
 
-use http::{Request, RequestBuilder, RequestType};
-use pool::PoolError;
+mod access_log;
+mod body;
+mod client_limits;
+mod connection;
+mod event_loop;
+mod handoff;
+mod health;
+mod listener;
+mod metrics;
+mod middleware;
+mod panics;
+mod proxy;
+mod reload;
+mod request_id;
+mod response_writer;
+mod virtual_hosts;
+mod workers;
+
+pub use self::access_log::{AccessLog, AccessLogFormat, Rotation};
+pub use self::body::RequestBody;
+pub use self::client_limits::{ClientLimits, InvalidNetwork, Network};
+pub use self::connection::ConnectionSettings;
+pub use self::handoff::inherited_listeners;
+pub use self::health::HealthChecks;
+pub use self::listener::{Listener, Stream};
+pub use self::middleware::{Flow, Middleware, MiddlewareChain};
+pub use self::proxy::Proxy;
+pub use self::request_id::{current_request_id, REQUEST_ID_HEADER};
+pub use self::response_writer::ResponseWriter;
+pub use self::virtual_hosts::VirtualHosts;
+pub use self::workers::Workers;
+
+use self::client_limits::ClientTracker;
+use self::event_loop::{EventLoop, IdleSender};
+use self::metrics::Metrics;
+use self::request_id::RequestIds;
+use self::virtual_hosts::SharedHosts;
+use self::workers::ServerWork;
+use http::{HttpVersion, Request};
+use pool::{PoolError, ThreadPoolBuilder};
 use router::{Endpoint, Router};
-use std::convert::TryFrom;
-use std::io::{BufRead, BufReader, Write};
-use std::net::{TcpListener, TcpStream};
-use std::sync::Arc;
+use std::net::{IpAddr, SocketAddr};
+use std::panic::RefUnwindSafe;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock, Weak};
 use std::time::Duration;
 
 /// An http server that takes care of accepting connections and serving them with content
 pub struct HttpServer {
-    listener: TcpListener,
-    router: Router<HttpRouteInfo, ()>,
+    listeners: Vec<Listener>,
+    hosts: VirtualHosts,
+    settings: ConnectionSettings,
+    reload: Option<ReloadHook>,
+    access_log: Option<AccessLog>,
+    metrics_path: Option<String>,
+    health_checks: Option<HealthChecks>,
+    middleware: MiddlewareChain,
+    client_limits: ClientLimits,
+    worker_start: Option<WorkerStartHook>,
+    /// Tasks run on the workers at an interval once the server listens
+    periodic: Vec<(Duration, PeriodicTask)>,
+}
+
+/// Called with the worker id on every worker thread before it serves connections
+pub type WorkerStartHook = Box<dyn Fn(usize) + Send + Sync>;
+
+/// Run on the workers at an interval, see `HttpServer::schedule_every`
+pub type PeriodicTask = Box<dyn Fn() + Send + Sync + RefUnwindSafe>;
+
+/// Builds the virtual hosts that replace the current ones when the server is reloaded
+/// It runs on a thread of its own
+pub type ReloadHook = Box<dyn Fn() -> Result<VirtualHosts, failure::Error> + Send + Sync>;
+
+/// State shared by the workers serving connections
+pub(crate) struct ServerState {
+    hosts: SharedHosts,
+    settings: ConnectionSettings,
+    access_log: Option<AccessLog>,
+    metrics: Arc<Metrics>,
+    /// Path metrics are served at, for every host
+    metrics_path: Option<String>,
+    health_checks: Option<HealthChecks>,
+    /// Middleware for every host, they are kept when the hosts are reloaded
+    middleware: MiddlewareChain,
+    clients: Arc<ClientTracker>,
+    request_ids: RequestIds,
+    idle: IdleSender,
+    /// The server is shutting down, connections are closed after their current request
+    draining: Arc<AtomicBool>,
+    /// Listeners connections are accepted on, none once draining
+    listening: AtomicUsize,
+    /// The last reload failed, the hosts may not be the expected ones
+    reload_failed: Arc<AtomicBool>,
+    /// Connections currently handled by a worker
+    in_flight: AtomicUsize,
+    /// Handle to the workers, set once they are started
+    workers: RwLock<Workers>,
+}
+
+/// Routes answered by the server itself instead of a router
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum BuiltinRoute {
+    Metrics,
+    Liveness,
+    Readiness,
+}
+
+impl ServerState {
+    pub fn workers(&self) -> Workers {
+        self.workers.read().unwrap().clone()
+    }
+
+    /// Built-in route served at `path`, if any
+    pub fn builtin_route(&self, path: &str) -> Option<BuiltinRoute> {
+        if self.metrics_path.as_deref() == Some(path) {
+            return Some(BuiltinRoute::Metrics);
+        }
+
+        let checks = self.health_checks.as_ref()?;
+        if checks.liveness_path == path {
+            Some(BuiltinRoute::Liveness)
+        } else if checks.readiness_path == path {
+            Some(BuiltinRoute::Readiness)
+        } else {
+            None
+        }
+    }
+
+    pub fn respond_builtin(
+        &self,
+        route: BuiltinRoute,
+        writer: &mut ResponseWriter,
+    ) -> std::io::Result<()> {
+        match (route, &self.health_checks) {
+            (BuiltinRoute::Metrics, _) => self.metrics.respond(writer),
+            (BuiltinRoute::Liveness, _) => health::respond_liveness(writer),
+            (BuiltinRoute::Readiness, Some(checks)) => {
+                health::respond_readiness(self, checks, writer)
+            }
+            (BuiltinRoute::Readiness, None) => unreachable!("readiness route without checks"),
+        }
+    }
+}
+
+#[cfg(test)]
+impl ServerState {
+    /// State of a server serving `hosts` with the default settings, without workers
+    /// Other fields can be set with the struct update syntax
+    pub fn for_tests(hosts: VirtualHosts) -> Self {
+        let (_, idle) = EventLoop::new(Vec::new(), Duration::from_secs(5), None).unwrap();
+        ServerState {
+            hosts: SharedHosts::new(hosts),
+            settings: ConnectionSettings::default(),
+            access_log: None,
+            metrics: Arc::new(Metrics::default()),
+            metrics_path: None,
+            health_checks: None,
+            middleware: MiddlewareChain::default(),
+            clients: Arc::new(ClientTracker::new(ClientLimits::default())),
+            request_ids: RequestIds::default(),
+            idle,
+            draining: Arc::new(AtomicBool::new(false)),
+            listening: AtomicUsize::new(0),
+            reload_failed: Arc::new(AtomicBool::new(false)),
+            in_flight: AtomicUsize::new(0),
+            workers: RwLock::new(Workers::new(Weak::new())),
+        }
+    }
+}
+
+/// Removes a connection from the in-flight count once dropped, even if handling it panicked
+struct InFlight<'a>(&'a AtomicUsize);
+
+impl<'a> Drop for InFlight<'a> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
 }
 
 /// Info that needs to be routed to an endpoint
 #[derive(Debug)]
 pub struct HttpRouteInfo {
     request: Request,
-    writer: TcpStream,
+    /// Either generated or taken from the X-Request-ID header of a trusted client
+    request_id: String,
+    writer: ResponseWriter,
+    body: RequestBody,
+    /// `None` for clients connected through a unix socket
+    client_ip: Option<IpAddr>,
+    /// The client is a trusted proxy, or connected through a unix socket
+    trusted_client: bool,
+    workers: Workers,
 }
 
 impl HttpRouteInfo {
@@ -35,10 +209,41 @@ impl HttpRouteInfo {
         &self.request
     }
 
-    pub fn writer(&mut self) -> &mut impl Write {
+    /// The request can be modified by middleware before it gets to the endpoint
+    /// The endpoint is found with the path the request has once every middleware ran
+    pub fn request_mut(&mut self) -> &mut Request {
+        &mut self.request
+    }
+
+    /// Unique ID of the request, also sent in the response's X-Request-ID header
+    pub fn request_id(&self) -> &str {
+        &self.request_id
+    }
+
+    pub fn writer(&mut self) -> &mut ResponseWriter {
         &mut self.writer
     }
 
+    pub fn body(&mut self) -> &mut RequestBody {
+        &mut self.body
+    }
+
+    /// Address of the client, `None` if it connected through a unix socket
+    pub fn client_ip(&self) -> Option<IpAddr> {
+        self.client_ip
+    }
+
+    /// Workers of the server, to run work such as CPU-bound parts of the response on
+    pub fn workers(&self) -> &Workers {
+        &self.workers
+    }
+
+    /// Respond with a fully formed response, such as the ones generated in `static_out`
+    pub fn prerendered(mut self, response: &[u8]) -> Result<(), HttpServerError> {
+        self.writer.prerendered(response)?;
+        Ok(())
+    }
+
     /// Respond with a 202 ok with the given body of content
     pub fn ok(mut self, content: &[u8]) -> Result<(), HttpServerError> {
         const HEADER: &[u8] = response_head!(
@@ -49,11 +254,7 @@ impl HttpRouteInfo {
             h("Cache-Control" => "public")
         ).as_bytes();
 
-        self.writer.write_all(HEADER)?;
-        self.writer
-            .write_all(&format!("Content-Length: {}\r\n\r\n", content.len()).into_bytes())?;
-        self.writer.write_all(content)?;
-
+        self.writer.respond(HEADER, content)?;
         Ok(())
     }
 
@@ -66,10 +267,7 @@ impl HttpRouteInfo {
             h("Cache-Control" => "public")
         ).as_bytes();
 
-        self.writer.write_all(HEADER)?;
-        self.writer
-            .write_all(&format!("Content-Length: {}\r\n\r\n", content.len()).into_bytes())?;
-        self.writer.write_all(content)?;
+        self.writer.respond(HEADER, content)?;
         Ok(())
     }
 
@@ -79,15 +277,11 @@ impl HttpRouteInfo {
             h("Content-Type" => "text/html charset=UTF-8"),
             h("Content-Encoding" => "gzip"),
             h("Cache-Control" => "max-age=1800"),
-            h("Cache-Control" => "public"),
-            h("Connection" => "Close")
+            h("Cache-Control" => "public")
         ).as_bytes();
 
-        self.writer.write_all(HEADER)?;
-        self.writer
-            .write_all(&format!("Content-Length: {}\r\n\r\n", content.len()).into_bytes())?;
-        self.writer.write_all(content)?;
-
+        self.writer.close();
+        self.writer.respond(HEADER, content)?;
         Ok(())
     }
 }
@@ -98,10 +292,135 @@ pub enum HttpServerError {
     IoError(std::io::Error),
     #[fail(display = "Http Method not present in request line")]
     HttpMethodNotPresent,
+    #[fail(display = "Unknown http method: {}", 0)]
+    UnknownMethod(String),
     #[fail(display = "Path not present in request line")]
     PathNotPresent,
+    #[fail(display = "Http version not present in request line")]
+    HttpVersionNotPresent,
+    #[fail(display = "Malformed http version in request line")]
+    InvalidHttpVersion,
+    #[fail(display = "Unsupported http version: {}", 0)]
+    UnsupportedHttpVersion(HttpVersion),
+    #[fail(display = "Host header not present in HTTP/1.1 request")]
+    HostNotPresent,
+    #[fail(display = "Request line or headers are not valid UTF-8")]
+    InvalidUtf8,
+    #[fail(display = "Invalid Content-Length header")]
+    InvalidContentLength,
+    #[fail(display = "Transfer-Encoding other than chunked")]
+    UnsupportedTransferEncoding,
+    #[fail(display = "Request line longer than the limit")]
+    RequestLineTooLong,
+    #[fail(display = "Request headers larger than the limit")]
+    HeadersTooLarge,
+    #[fail(display = "More request headers than the limit")]
+    TooManyHeaders,
+    #[fail(display = "Request line and headers not received in time")]
+    RequestTimeout,
+    #[fail(display = "Client has too many connections open")]
+    TooManyConnections,
+    #[fail(display = "Client over its request rate, retry after {} seconds", 0)]
+    RateLimited(u64),
+    #[fail(display = "Every worker is busy and the queue is full")]
+    Overloaded,
+    #[fail(display = "The endpoint panicked")]
+    EndpointPanicked,
+    #[fail(display = "No virtual host for: {}", 0)]
+    UnknownHost(String),
+    #[fail(display = "The server has no listener to accept connections on")]
+    NoListeners,
     #[fail(display = "Thread pool error")]
     ThreadPoolError(PoolError),
+    #[fail(display = "The workers were stopped")]
+    WorkersStopped,
+}
+
+impl HttpServerError {
+    /// Status of the response sent to the client when this error comes from a bad request
+    /// Returns `None` if no response can be sent
+    pub fn response_status(&self) -> Option<&'static str> {
+        match self {
+            HttpServerError::HttpMethodNotPresent
+            | HttpServerError::PathNotPresent
+            | HttpServerError::HttpVersionNotPresent
+            | HttpServerError::InvalidHttpVersion
+            | HttpServerError::HostNotPresent
+            | HttpServerError::InvalidUtf8
+            | HttpServerError::InvalidContentLength => Some("400 Bad Request"),
+            HttpServerError::UnknownMethod(_) | HttpServerError::UnsupportedTransferEncoding => {
+                Some("501 Not Implemented")
+            }
+            HttpServerError::UnsupportedHttpVersion(_) => Some("505 HTTP Version Not Supported"),
+            HttpServerError::UnknownHost(_) => Some("421 Misdirected Request"),
+            HttpServerError::RequestLineTooLong => Some("414 URI Too Long"),
+            HttpServerError::HeadersTooLarge | HttpServerError::TooManyHeaders => {
+                Some("431 Request Header Fields Too Large")
+            }
+            HttpServerError::RequestTimeout => Some("408 Request Timeout"),
+            HttpServerError::TooManyConnections | HttpServerError::RateLimited(_) => {
+                Some("429 Too Many Requests")
+            }
+            HttpServerError::Overloaded => Some("503 Service Unavailable"),
+            HttpServerError::EndpointPanicked => Some("500 Internal Server Error"),
+            HttpServerError::IoError(_)
+            | HttpServerError::NoListeners
+            | HttpServerError::ThreadPoolError(_)
+            | HttpServerError::WorkersStopped => None,
+        }
+    }
+
+    /// Whether the request was refused because it went over a limit of `ConnectionSettings`
+    pub fn is_limit(&self) -> bool {
+        match self {
+            HttpServerError::RequestLineTooLong
+            | HttpServerError::HeadersTooLarge
+            | HttpServerError::TooManyHeaders
+            | HttpServerError::RequestTimeout
+            | HttpServerError::TooManyConnections
+            | HttpServerError::RateLimited(_)
+            | HttpServerError::Overloaded => true,
+            _ => false,
+        }
+    }
+
+    /// Seconds the client should wait before retrying, sent in the Retry-After header
+    pub fn retry_after(&self) -> Option<u64> {
+        match self {
+            HttpServerError::TooManyConnections | HttpServerError::Overloaded => Some(1),
+            HttpServerError::RateLimited(seconds) => Some(*seconds),
+            _ => None,
+        }
+    }
+
+    /// Name of the variant, used to label metrics
+    pub fn name(&self) -> &'static str {
+        match self {
+            HttpServerError::IoError(_) => "IoError",
+            HttpServerError::HttpMethodNotPresent => "HttpMethodNotPresent",
+            HttpServerError::UnknownMethod(_) => "UnknownMethod",
+            HttpServerError::PathNotPresent => "PathNotPresent",
+            HttpServerError::HttpVersionNotPresent => "HttpVersionNotPresent",
+            HttpServerError::InvalidHttpVersion => "InvalidHttpVersion",
+            HttpServerError::UnsupportedHttpVersion(_) => "UnsupportedHttpVersion",
+            HttpServerError::HostNotPresent => "HostNotPresent",
+            HttpServerError::InvalidUtf8 => "InvalidUtf8",
+            HttpServerError::InvalidContentLength => "InvalidContentLength",
+            HttpServerError::UnsupportedTransferEncoding => "UnsupportedTransferEncoding",
+            HttpServerError::RequestLineTooLong => "RequestLineTooLong",
+            HttpServerError::HeadersTooLarge => "HeadersTooLarge",
+            HttpServerError::TooManyHeaders => "TooManyHeaders",
+            HttpServerError::RequestTimeout => "RequestTimeout",
+            HttpServerError::TooManyConnections => "TooManyConnections",
+            HttpServerError::RateLimited(_) => "RateLimited",
+            HttpServerError::Overloaded => "Overloaded",
+            HttpServerError::EndpointPanicked => "EndpointPanicked",
+            HttpServerError::UnknownHost(_) => "UnknownHost",
+            HttpServerError::NoListeners => "NoListeners",
+            HttpServerError::ThreadPoolError(_) => "ThreadPoolError",
+            HttpServerError::WorkersStopped => "WorkersStopped",
+        }
+    }
 }
 
 impl From<std::io::Error> for HttpServerError {
@@ -111,114 +430,246 @@ impl From<std::io::Error> for HttpServerError {
 }
 
 impl HttpServer {
-    /// Create an http server on the specified port
+    /// Create an http server on the specified port of every IPv4 interface
     /// `valid` valid port. Should be 80 for http
-    pub fn create(port: usize) -> Result<Self, HttpServerError> {
-        Ok(Self {
-            listener: TcpListener::bind(&format!("0.0.0.0:{}", port))?,
-            router: Router::default(),
-        })
+    pub fn create(port: u16) -> Result<Self, HttpServerError> {
+        Self::bind(&[SocketAddr::from(([0, 0, 0, 0], port))])
+    }
+
+    /// Create an http server listening on every address of `addrs`
+    /// `[::]` listens on every IPv4 and IPv6 interface
+    pub fn bind(addrs: &[SocketAddr]) -> Result<Self, HttpServerError> {
+        let mut server = Self::from_listeners(Vec::new());
+
+        for addr in addrs {
+            server.add_listener(Listener::bind_tcp(*addr)?);
+        }
+
+        Ok(server)
+    }
+
+    /// Create an http server on an already bound listener
+    pub fn from_listener(listener: impl Into<Listener>) -> Self {
+        Self::from_listeners(vec![listener.into()])
+    }
+
+    /// Create an http server on already bound listeners, such as the ones from `inherited_listeners`
+    pub fn from_listeners(listeners: Vec<Listener>) -> Self {
+        Self {
+            listeners,
+            hosts: VirtualHosts::default(),
+            settings: ConnectionSettings::default(),
+            reload: None,
+            access_log: None,
+            metrics_path: None,
+            health_checks: None,
+            middleware: MiddlewareChain::default(),
+            worker_start: None,
+            client_limits: ClientLimits::default(),
+            periodic: Vec::new(),
+        }
+    }
+
+    /// Also accept connections on `listener`
+    /// Every listener is served by the same routers and workers
+    pub fn add_listener(&mut self, listener: impl Into<Listener>) {
+        self.listeners.push(listener.into());
     }
 
     /// Listen and respond to incoming http requests
+    ///
+    /// Connections are watched by an event loop on the calling thread and are only handed to one of
+    /// the `worker_num` workers once a request is ready to be read.
+    /// Once `max_queued` connections are waiting for a worker, new requests are answered with a
+    /// 503 and the connection is closed.
+    /// If the settings allow more workers, they are added while connections wait for one and
+    /// retire once idle.
+    ///
+    /// On SIGHUP, the virtual hosts are replaced by the ones built by the hook given to `on_reload`.
+    ///
+    /// Returns `Ok(())` after a graceful shutdown, triggered by SIGTERM or SIGINT,
+    /// or by SIGUSR2 once a new server process has taken over the listening socket.
+    /// Requests that are still running once the drain timeout is reached are abandoned.
     pub fn listen(self, worker_num: usize) -> Result<(), HttpServerError> {
-        let HttpServer { listener, router } = self;
-        let router = Arc::new(router);
+        let HttpServer {
+            listeners,
+            hosts,
+            settings,
+            reload,
+            access_log,
+            metrics_path,
+            health_checks,
+            middleware,
+            client_limits,
+            worker_start,
+            periodic,
+        } = self;
+
+        if listeners.is_empty() {
+            return Err(HttpServerError::NoListeners);
+        }
 
-        let workers = pool::ThreadPool::new(worker_num, router);
+        let (event_loop, idle) = EventLoop::new(listeners, settings.keep_alive_timeout, reload)?;
+        let state = Arc::new(ServerState {
+            hosts: SharedHosts::new(hosts),
+            settings,
+            access_log,
+            metrics: Arc::new(Metrics::default()),
+            metrics_path,
+            health_checks,
+            middleware,
+            clients: Arc::new(ClientTracker::new(client_limits)),
+            request_ids: RequestIds::default(),
+            idle,
+            draining: Arc::new(AtomicBool::new(false)),
+            listening: AtomicUsize::new(0),
+            reload_failed: Arc::new(AtomicBool::new(false)),
+            in_flight: AtomicUsize::new(0),
+            workers: RwLock::new(Workers::new(Weak::new())),
+        });
+
+        let settings = &state.settings;
+        let mut workers = ThreadPoolBuilder::new(worker_num);
+        workers
+            .max_queued(settings.max_queued)
+            .name_prefix("http-worker")
+            .pin_to_cpus(&settings.worker_cpus);
+        if let Some(max_workers) = settings.max_workers {
+            if max_workers > worker_num {
+                workers.elastic(max_workers, settings.worker_idle_timeout);
+            }
+        }
+        if let Some(stack_size) = settings.worker_stack_size {
+            workers.stack_size(stack_size);
+        }
+        if settings.work_stealing {
+            workers.work_stealing();
+        }
+        if let Some(hook) = worker_start {
+            workers.on_start(move |id| hook(id));
+        }
+        let workers = Arc::new(workers.build(state.clone()));
+        *state.workers.write().unwrap() = Workers::new(Arc::downgrade(&workers));
+        state.metrics.watch_pool(workers.stats());
+        for (interval, task) in periodic {
+            workers.schedule_every(interval, move |_: &Arc<ServerState>| task());
+        }
+
+        let served = event_loop.run(&state, |connection| {
+            // Counted before being queued so the drain also waits for queued connections
+            state.in_flight.fetch_add(1, Ordering::SeqCst);
 
-        for stream in listener.incoming() {
-            let stream = stream?;
+            // The connection is given back if it can't be queued, it is answered then closed
+            if let Err(work) = workers.try_do_work(ServerWork::Serve(connection)) {
+                state.in_flight.fetch_sub(1, Ordering::SeqCst);
 
-            workers.do_work(move |router: &Arc<Router<HttpRouteInfo, ()>>| {
-                if let Err(err) = Self::handle_connection(stream, router) {
-                    println!("Error in request: {:?}", err);
+                if let ServerWork::Serve(connection) = work {
+                    connection::refuse(
+                        connection.stream(),
+                        &HttpServerError::Overloaded,
+                        &state.metrics,
+                    );
                 }
-            });
+            }
+        });
+
+        // Scheduled tasks stop with the server, even if its workers are not joined
+        workers.cancel_scheduled();
+        served?;
+
+        // Workers can only be joined once they are done, which is not the case if the drain timed out
+        // Handles given to endpoints only hold on to the pool while they submit work
+        if state.in_flight.load(Ordering::SeqCst) == 0 {
+            if let Ok(workers) = Arc::try_unwrap(workers) {
+                workers.join().map_err(HttpServerError::ThreadPoolError)?;
+            }
         }
 
-        workers.join().map_err(HttpServerError::ThreadPoolError)?;
         Ok(())
     }
 
+    /// Add a route to the default router
     pub fn add_route(
         &mut self,
         path: impl Into<router::RouterPath>,
         endpoint: impl Endpoint<HttpRouteInfo, ()> + 'static,
     ) {
-        self.router.add_path(path, endpoint);
+        self.hosts.default_mut().add_path(path, endpoint);
     }
 
+    /// Router used for requests that don't match any virtual host
     pub fn router_mut(&mut self) -> &mut Router<HttpRouteInfo, ()> {
-        &mut self.router
+        self.hosts.default_mut()
     }
 
-    /// Handles an incoming connection
-    /// Parses the request and responds
-    fn handle_connection(
-        stream: TcpStream,
-        router: &Arc<Router<HttpRouteInfo, ()>>,
-    ) -> Result<(), HttpServerError> {
-        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
-
-        let mut buffered_stream = BufReader::new(&stream);
-
-        // First line of a request, normally in the format "GET / HTTP/1.1"
-        let mut request_line = String::new();
-        buffered_stream.read_line(&mut request_line)?;
-
-        let mut parts = request_line.split_whitespace();
-        let request_type = parts.next().ok_or(HttpServerError::HttpMethodNotPresent)?;
-        let path = parts.next().ok_or(HttpServerError::PathNotPresent)?;
-
-        let mut request = RequestBuilder::new(
-            RequestType::try_from(request_type).unwrap_or(RequestType::GET),
-            "localhost",
-        );
-        request.path(path);
-
-        let mut persist = true;
+    /// Router for requests to `host`, e.g. "example.com" or "*.example.com"
+    pub fn virtual_host_mut(&mut self, host: &str) -> &mut Router<HttpRouteInfo, ()> {
+        self.hosts.host_mut(host)
+    }
 
-        // Parse all the headers
-        let mut line = String::new();
-        loop {
-            buffered_stream.read_line(&mut line)?;
+    pub fn virtual_hosts_mut(&mut self) -> &mut VirtualHosts {
+        &mut self.hosts
+    }
 
-            if line.trim().is_empty() {
-                break;
-            }
+    /// Middleware for every host, which run before the middleware of the host
+    /// Built-in routes, such as metrics and health checks, don't go through middleware
+    pub fn middleware_mut(&mut self) -> &mut MiddlewareChain {
+        &mut self.middleware
+    }
 
-            if let Some(header_split_index) = line.find(':') {
-                let (name, value) = line.split_at(header_split_index);
-                let value = value[1..].trim();
+    pub fn settings_mut(&mut self) -> &mut ConnectionSettings {
+        &mut self.settings
+    }
 
-                if "connection" == name.to_lowercase().trim()
-                    && "close" == value.to_lowercase().trim()
-                {
-                    persist = false;
-                }
+    /// Limit the connections and request rate of every client
+    pub fn set_client_limits(&mut self, limits: ClientLimits) {
+        self.client_limits = limits;
+    }
 
-                request.header(name, value);
-            }
+    /// Write a line to `access_log` for every request served
+    pub fn set_access_log(&mut self, access_log: AccessLog) {
+        self.access_log = Some(access_log);
+    }
 
-            // We reuse the line buffer, so we need to clear it every time
-            line.clear();
-        }
+    /// Serve metrics in the Prometheus text format at `path`, e.g. "/metrics"
+    /// The path is served for every host, before any router
+    pub fn set_metrics_path(&mut self, path: &str) {
+        self.metrics_path = Some(path.to_string());
+    }
 
-        let request = request.build();
+    /// Serve liveness and readiness checks, e.g. at "/healthz" and "/readyz"
+    pub fn set_health_checks(&mut self, checks: HealthChecks) {
+        self.health_checks = Some(checks);
+    }
 
-        let _ = router.route(
-            path,
-            HttpRouteInfo {
-                writer: stream.try_clone()?,
-                request,
-            },
-        );
+    /// Set how virtual hosts are rebuilt when the server receives SIGHUP, e.g. from a config file
+    ///
+    /// The hook runs on a thread of its own, connections are served by the current hosts
+    /// meanwhile. The new hosts are swapped in atomically once built, requests already being
+    /// served finish with the routers they started with. If the hook fails, the error is logged
+    /// and the current hosts are kept.
+    pub fn on_reload(
+        &mut self,
+        hook: impl Fn() -> Result<VirtualHosts, failure::Error> + Send + Sync + 'static,
+    ) {
+        self.reload = Some(Box::new(hook));
+    }
 
-        if persist {
-            Self::handle_connection(stream, router)?;
-        }
+    /// Run `task` on the workers every `interval` once the server listens, e.g. to warm a cache
+    /// or flush stats
+    /// Endpoints can schedule tasks as well, through `HttpRouteInfo::workers`
+    pub fn schedule_every(
+        &mut self,
+        interval: Duration,
+        task: impl Fn() + Send + Sync + RefUnwindSafe + 'static,
+    ) {
+        self.periodic.push((interval, Box::new(task)));
+    }
 
-        Ok(())
+    /// Call `hook` on every worker thread before it serves connections, e.g. to set up
+    /// thread locals such as buffer pools
+    /// `hook` is given the id of the worker, workers added to an elastic pool get new ids
+    pub fn on_worker_start(&mut self, hook: impl Fn(usize) + Send + Sync + 'static) {
+        self.worker_start = Some(Box::new(hook));
     }
 }
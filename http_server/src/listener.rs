@@ -0,0 +1,210 @@
+use net2::TcpBuilder;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::time::Duration;
+
+/// Pending connections queued by the kernel before they are accepted
+const BACKLOG: i32 = 128;
+
+/// A socket the server accepts connections on
+#[derive(Debug)]
+pub enum Listener {
+    Tcp(TcpListener),
+    /// Local socket, e.g. for a reverse proxy running on the same machine
+    Unix(UnixListener),
+}
+
+impl Listener {
+    /// Bind a tcp socket
+    /// The unspecified IPv6 address (`[::]`) is bound dual-stack so it also accepts IPv4 clients
+    pub fn bind_tcp(addr: SocketAddr) -> io::Result<Self> {
+        let builder = match addr {
+            SocketAddr::V4(_) => TcpBuilder::new_v4()?,
+            SocketAddr::V6(v6) => {
+                let builder = TcpBuilder::new_v6()?;
+                builder.only_v6(!v6.ip().is_unspecified())?;
+                builder
+            }
+        };
+
+        builder.reuse_address(true)?;
+        builder.bind(addr)?;
+
+        Ok(Listener::Tcp(builder.listen(BACKLOG)?))
+    }
+
+    /// Bind a unix domain socket
+    /// A socket file left at `path` by a previous server is replaced
+    pub fn bind_unix(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+
+        Ok(Listener::Unix(UnixListener::bind(path)?))
+    }
+
+    pub fn accept(&self) -> io::Result<Stream> {
+        match self {
+            Listener::Tcp(listener) => listener.accept().map(|(stream, _)| Stream::Tcp(stream)),
+            Listener::Unix(listener) => listener.accept().map(|(stream, _)| Stream::Unix(stream)),
+        }
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Listener::Tcp(listener) => listener.set_nonblocking(nonblocking),
+            Listener::Unix(listener) => listener.set_nonblocking(nonblocking),
+        }
+    }
+}
+
+impl From<TcpListener> for Listener {
+    fn from(listener: TcpListener) -> Self {
+        Listener::Tcp(listener)
+    }
+}
+
+impl From<UnixListener> for Listener {
+    fn from(listener: UnixListener) -> Self {
+        Listener::Unix(listener)
+    }
+}
+
+impl AsRawFd for Listener {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Listener::Tcp(listener) => listener.as_raw_fd(),
+            Listener::Unix(listener) => listener.as_raw_fd(),
+        }
+    }
+}
+
+/// A client connection accepted by a `Listener`
+#[derive(Debug)]
+pub enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Stream {
+    pub fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            Stream::Tcp(stream) => stream.try_clone().map(Stream::Tcp),
+            Stream::Unix(stream) => stream.try_clone().map(Stream::Unix),
+        }
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_read_timeout(timeout),
+            Stream::Unix(stream) => stream.set_read_timeout(timeout),
+        }
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_nonblocking(nonblocking),
+            Stream::Unix(stream) => stream.set_nonblocking(nonblocking),
+        }
+    }
+
+    /// Address the client connected to
+    /// Returns `None` for unix sockets
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Stream::Tcp(stream) => stream.local_addr().ok(),
+            Stream::Unix(_) => None,
+        }
+    }
+
+    /// Address of the client
+    /// Returns `None` for unix sockets
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Stream::Tcp(stream) => stream.peer_addr().ok(),
+            Stream::Unix(_) => None,
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buf),
+            Stream::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buf),
+            Stream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            Stream::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+impl AsRawFd for Stream {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Stream::Tcp(stream) => stream.as_raw_fd(),
+            Stream::Unix(stream) => stream.as_raw_fd(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client_limits::canonical_ip;
+    use std::env;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::process;
+
+    #[test]
+    fn replace_stale_unix_socket() {
+        let dir = env::temp_dir().join(format!("milton-listener-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("server.sock");
+
+        // The socket file stays once its listener is closed
+        drop(Listener::bind_unix(&path).unwrap());
+        assert!(path.exists());
+
+        let listener = Listener::bind_unix(&path).unwrap();
+        let _client = UnixStream::connect(&path).unwrap();
+        let stream = listener.accept().unwrap();
+        assert_eq!(stream.peer_addr(), None);
+        assert_eq!(stream.local_addr(), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn dual_stack() {
+        let listener = Listener::bind_tcp("[::]:0".parse().unwrap()).unwrap();
+        let port = match &listener {
+            Listener::Tcp(listener) => listener.local_addr().unwrap().port(),
+            Listener::Unix(_) => unreachable!(),
+        };
+
+        let _client = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+        let stream = listener.accept().unwrap();
+        let peer = stream.peer_addr().unwrap();
+        assert_eq!(canonical_ip(peer.ip()), IpAddr::from(Ipv4Addr::LOCALHOST));
+    }
+}
@@ -0,0 +1,368 @@
+use crate::response_writer::ResponseWriter;
+use crate::HttpServerError;
+use http::ResponseBuilder;
+use pool::PoolStats;
+use std::collections::HashMap;
+use std::fmt::Write as FmtWrite;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Upper bounds of the request duration buckets, in seconds
+const DURATION_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Counters of everything the server does, exposed in the Prometheus text format
+#[derive(Default)]
+pub(crate) struct Metrics {
+    requests: Mutex<HashMap<RequestLabels, RequestStats>>,
+    requests_in_flight: AtomicUsize,
+    open_connections: AtomicUsize,
+    bytes_received: AtomicUsize,
+    bytes_sent: AtomicUsize,
+    /// Requests that could not be handled, by `HttpServerError` variant
+    errors: Mutex<HashMap<&'static str, usize>>,
+    /// Set once the worker pool is started
+    pool: Mutex<Option<Arc<PoolStats>>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct RequestLabels {
+    /// Route the request matched, see `Router::route_name`
+    /// Empty for requests that did not match a route
+    route: String,
+    method: String,
+    /// `None` if the endpoint did not write a response
+    status: Option<u16>,
+}
+
+#[derive(Debug, Default)]
+struct RequestStats {
+    count: usize,
+    /// Sum of durations in seconds
+    duration_sum: f64,
+    /// Requests that took at most the matching bound of `DURATION_BUCKETS`, not cumulative
+    buckets: [usize; DURATION_BUCKETS.len()],
+}
+
+/// Decrements a gauge once dropped
+pub(crate) struct GaugeGuard<'a>(&'a AtomicUsize);
+
+impl<'a> Drop for GaugeGuard<'a> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Counts a connection as open until it is dropped
+pub(crate) struct OpenConnection(Arc<Metrics>);
+
+impl Drop for OpenConnection {
+    fn drop(&mut self) {
+        self.0.open_connections.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Metrics {
+    pub fn watch_pool(&self, stats: Arc<PoolStats>) {
+        *self.pool.lock().unwrap() = Some(stats);
+    }
+
+    /// Work waiting for a worker, 0 until the pool is started
+    pub fn queued(&self) -> usize {
+        self.pool
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map_or(0, |pool| pool.queued())
+    }
+
+    pub fn connection_opened(metrics: &Arc<Metrics>) -> OpenConnection {
+        metrics.open_connections.fetch_add(1, Ordering::SeqCst);
+        OpenConnection(metrics.clone())
+    }
+
+    /// The request is counted as in flight until the guard is dropped
+    pub fn request_started(&self) -> GaugeGuard {
+        self.requests_in_flight.fetch_add(1, Ordering::SeqCst);
+        GaugeGuard(&self.requests_in_flight)
+    }
+
+    pub fn request_done(&self, route: &str, method: &str, status: Option<u16>, duration: Duration) {
+        let seconds = duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1e9;
+        let labels = RequestLabels {
+            route: route.to_string(),
+            method: method.to_string(),
+            status,
+        };
+
+        let mut requests = self.requests.lock().unwrap();
+        let stats = requests.entry(labels).or_default();
+        stats.count += 1;
+        stats.duration_sum += seconds;
+        if let Some(bucket) = DURATION_BUCKETS.iter().position(|bound| seconds <= *bound) {
+            stats.buckets[bucket] += 1;
+        }
+    }
+
+    pub fn received(&self, bytes: usize) {
+        self.bytes_received.fetch_add(bytes, Ordering::SeqCst);
+    }
+
+    pub fn sent(&self, bytes: usize) {
+        self.bytes_sent.fetch_add(bytes, Ordering::SeqCst);
+    }
+
+    /// Returns how many errors of this kind there were so far
+    pub fn error(&self, err: &HttpServerError) -> usize {
+        let mut errors = self.errors.lock().unwrap();
+        let count = errors.entry(err.name()).or_insert(0);
+        *count += 1;
+        *count
+    }
+
+    /// Respond with every metric
+    pub fn respond(&self, writer: &mut ResponseWriter) -> io::Result<()> {
+        let mut response = ResponseBuilder::ok_200();
+        response
+            .header("Content-Type", "text/plain; version=0.0.4")
+            .header("Cache-Control", "no-store")
+            .body(self.render().into_bytes());
+        let response = response.build();
+
+        writer.respond(&response.head_bytes(), response.body())
+    }
+
+    /// Every metric in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        let requests = self.requests.lock().unwrap();
+        let mut labels: Vec<_> = requests.keys().collect();
+        labels.sort();
+
+        header(
+            &mut out,
+            "http_requests_total",
+            "counter",
+            "Requests served",
+        );
+        for label in &labels {
+            let _ = writeln!(
+                out,
+                "http_requests_total{{{}}} {}",
+                label.render(),
+                requests[*label].count
+            );
+        }
+
+        header(
+            &mut out,
+            "http_request_duration_seconds",
+            "histogram",
+            "Time taken to serve requests",
+        );
+        for label in &labels {
+            let stats = &requests[*label];
+            let label = label.render();
+
+            let mut cumulative = 0;
+            for (bound, count) in DURATION_BUCKETS.iter().zip(stats.buckets.iter()) {
+                cumulative += count;
+                let _ = writeln!(
+                    out,
+                    "http_request_duration_seconds_bucket{{{},le=\"{}\"}} {}",
+                    label, bound, cumulative
+                );
+            }
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}",
+                label, stats.count
+            );
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_sum{{{}}} {}",
+                label, stats.duration_sum
+            );
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_count{{{}}} {}",
+                label, stats.count
+            );
+        }
+        drop(requests);
+
+        let gauges = [
+            (
+                "http_requests_in_flight",
+                "gauge",
+                "Requests being served",
+                &self.requests_in_flight,
+            ),
+            (
+                "http_open_connections",
+                "gauge",
+                "Client connections open, idle or not",
+                &self.open_connections,
+            ),
+            (
+                "http_received_bytes_total",
+                "counter",
+                "Bytes of requests read",
+                &self.bytes_received,
+            ),
+            (
+                "http_sent_bytes_total",
+                "counter",
+                "Bytes of responses written",
+                &self.bytes_sent,
+            ),
+        ];
+        for (name, kind, help, value) in gauges.iter() {
+            header(&mut out, name, kind, help);
+            let _ = writeln!(out, "{} {}", name, value.load(Ordering::SeqCst));
+        }
+
+        let errors = self.errors.lock().unwrap();
+        let mut names: Vec<_> = errors.keys().collect();
+        names.sort();
+
+        header(
+            &mut out,
+            "http_parse_errors_total",
+            "counter",
+            "Requests that could not be parsed or served, by HttpServerError variant",
+        );
+        for name in names {
+            let _ = writeln!(
+                out,
+                "http_parse_errors_total{{error=\"{}\"}} {}",
+                name, errors[name]
+            );
+        }
+        drop(errors);
+
+        if let Some(pool) = &*self.pool.lock().unwrap() {
+            header(
+                &mut out,
+                "pool_worker_panics_total",
+                "counter",
+                "Work that panicked in a worker thread, by worker",
+            );
+            for (worker, panics) in pool.worker_panics() {
+                let _ = writeln!(
+                    out,
+                    "pool_worker_panics_total{{worker=\"{}\"}} {}",
+                    worker, panics
+                );
+            }
+
+            header(
+                &mut out,
+                "pool_queue_depth",
+                "gauge",
+                "Work waiting for a worker thread",
+            );
+            let _ = writeln!(out, "pool_queue_depth {}", pool.queued());
+
+            header(&mut out, "pool_workers", "gauge", "Worker threads running");
+            let _ = writeln!(out, "pool_workers {}", pool.workers());
+
+            header(
+                &mut out,
+                "pool_workers_busy",
+                "gauge",
+                "Worker threads serving a connection",
+            );
+            let _ = writeln!(out, "pool_workers_busy {}", pool.busy());
+
+            header(
+                &mut out,
+                "pool_utilization",
+                "gauge",
+                "Share of the worker threads that are busy",
+            );
+            let _ = writeln!(out, "pool_utilization {}", pool.utilization());
+        }
+
+        out
+    }
+}
+
+impl RequestLabels {
+    fn render(&self) -> String {
+        format!(
+            "route=\"{}\",method=\"{}\",status=\"{}\"",
+            escape(&self.route),
+            escape(&self.method),
+            self.status
+                .map(|status| status.to_string())
+                .unwrap_or_default()
+        )
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Escape a label value
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_requests() {
+        let metrics = Metrics::default();
+        metrics.request_done("/", "GET", Some(200), Duration::from_millis(20));
+        metrics.request_done("/", "GET", Some(200), Duration::from_millis(200));
+        metrics.request_done("/blog/*", "GET", Some(404), Duration::from_millis(1));
+        metrics.error(&HttpServerError::InvalidHttpVersion);
+
+        let rendered = metrics.render();
+        let route = "route=\"/\",method=\"GET\",status=\"200\"";
+
+        assert!(rendered.contains(&format!("http_requests_total{{{}}} 2\n", route)));
+        assert!(rendered.contains(&format!(
+            "http_request_duration_seconds_bucket{{{},le=\"0.01\"}} 0\n",
+            route
+        )));
+        assert!(rendered.contains(&format!(
+            "http_request_duration_seconds_bucket{{{},le=\"0.025\"}} 1\n",
+            route
+        )));
+        assert!(rendered.contains(&format!(
+            "http_request_duration_seconds_bucket{{{},le=\"+Inf\"}} 2\n",
+            route
+        )));
+        assert!(rendered
+            .contains("http_requests_total{route=\"/blog/*\",method=\"GET\",status=\"404\"} 1\n"));
+        assert!(rendered.contains("http_parse_errors_total{error=\"InvalidHttpVersion\"} 1\n"));
+    }
+
+    #[test]
+    fn gauges() {
+        let metrics = Arc::new(Metrics::default());
+
+        let connection = Metrics::connection_opened(&metrics);
+        let request = metrics.request_started();
+        assert!(metrics.render().contains("http_open_connections 1\n"));
+        assert!(metrics.render().contains("http_requests_in_flight 1\n"));
+
+        drop(request);
+        drop(connection);
+        assert!(metrics.render().contains("http_open_connections 0\n"));
+        assert!(metrics.render().contains("http_requests_in_flight 0\n"));
+    }
+}
@@ -0,0 +1,160 @@
+use crate::HttpRouteInfo;
+use http::{Request, Response};
+use std::fmt::{self, Debug};
+use std::panic::RefUnwindSafe;
+use std::sync::Arc;
+
+/// What happens to a request once a middleware saw it
+pub enum Flow {
+    /// Go on with the next middleware, then the endpoint
+    Continue,
+    /// Skip the remaining middleware and the endpoint, and send this response instead
+    Respond(Response),
+}
+
+/// Code that runs around endpoints, for concerns such as headers, auth or compression
+///
+/// `before` is called in the order middleware were added, global middleware first, then the
+/// ones of the virtual host. `after` is called in the reverse order, and only for the middleware
+/// whose `before` let the request through.
+///
+/// The virtual host and the middleware that run are picked with the host and path the client
+/// sent. The endpoint is routed once every `before` ran, so a middleware can rewrite the path
+/// with `Request::set_path`.
+pub trait Middleware: Send + Sync + RefUnwindSafe {
+    /// Inspect or modify the request before it gets to the endpoint
+    fn before(&self, _info: &mut HttpRouteInfo) -> Flow {
+        Flow::Continue
+    }
+
+    /// Modify the response before it is sent
+    /// Only called for responses written with `ResponseWriter::respond` or `prerendered`, streamed
+    /// responses and bytes written directly to the writer are sent as is
+    fn after(&self, _request: &Request, _response: &mut Response) {}
+}
+
+/// Middleware, each either for every path or for the paths under a prefix
+#[derive(Clone, Default)]
+pub struct MiddlewareChain {
+    /// Middleware with the prefix they apply to, in the order they were added
+    middleware: Vec<(Option<String>, Arc<dyn Middleware>)>,
+}
+
+impl MiddlewareChain {
+    /// Add a middleware for every path
+    pub fn add(&mut self, middleware: impl Middleware + 'static) -> &mut Self {
+        self.middleware.push((None, Arc::new(middleware)));
+        self
+    }
+
+    /// Add a middleware for the paths under `prefix`, e.g. "/api" for "/api" and "/api/users"
+    pub fn add_for_prefix(
+        &mut self,
+        prefix: &str,
+        middleware: impl Middleware + 'static,
+    ) -> &mut Self {
+        let prefix = prefix.trim_end_matches('/').to_string();
+        self.middleware.push((Some(prefix), Arc::new(middleware)));
+        self
+    }
+
+    /// Middleware that apply to `path`, in order
+    /// The query of the path is not matched against the prefixes
+    pub(crate) fn matching<'a>(
+        &'a self,
+        path: &'a str,
+    ) -> impl Iterator<Item = &'a Arc<dyn Middleware>> + 'a {
+        let path = path.split('?').next().unwrap_or(path);
+
+        self.middleware
+            .iter()
+            .filter(move |(prefix, _)| match prefix {
+                None => true,
+                Some(prefix) => {
+                    path.starts_with(prefix.as_str())
+                        && (path.len() == prefix.len() || path[prefix.len()..].starts_with('/'))
+                }
+            })
+            .map(|(_, middleware)| middleware)
+    }
+}
+
+impl Debug for MiddlewareChain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list()
+            .entries(self.middleware.iter().map(|(prefix, _)| prefix))
+            .finish()
+    }
+}
+
+/// Middleware that post-process a response, kept by the response writer
+pub(crate) struct ResponseFilters {
+    /// The request as the middleware left it
+    pub request: Request,
+    /// Middleware in the order their `before` was called
+    pub middleware: Vec<Arc<dyn Middleware>>,
+}
+
+impl ResponseFilters {
+    pub fn apply(&self, response: &mut Response) {
+        for middleware in self.middleware.iter().rev() {
+            middleware.after(&self.request, response);
+        }
+    }
+}
+
+impl Debug for ResponseFilters {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ResponseFilters")
+            .field("request", &self.request)
+            .field("middleware", &self.middleware.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::{RequestBuilder, RequestType, ResponseBuilder};
+    use std::sync::Mutex;
+
+    /// Records its name when it post-processes a response
+    struct Tag(&'static str, Arc<Mutex<Vec<&'static str>>>);
+
+    impl Middleware for Tag {
+        fn after(&self, _: &Request, response: &mut Response) {
+            self.1.lock().unwrap().push(self.0);
+            response
+                .headers_mut()
+                .add("X-Tag".to_string(), self.0.to_string());
+        }
+    }
+
+    #[test]
+    fn matching_order() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let mut chain = MiddlewareChain::default();
+        chain
+            .add(Tag("global", calls.clone()))
+            .add_for_prefix("/api/", Tag("api", calls.clone()))
+            .add(Tag("last", calls.clone()));
+
+        let filters = ResponseFilters {
+            request: RequestBuilder::new(RequestType::GET, "localhost").build(),
+            middleware: chain.matching("/api/users").cloned().collect(),
+        };
+        let mut response = ResponseBuilder::ok_200().build();
+        filters.apply(&mut response);
+
+        assert_eq!(*calls.lock().unwrap(), vec!["last", "api", "global"]);
+        assert_eq!(response.headers().get("x-tag"), Some("last"));
+
+        assert_eq!(chain.matching("/api").count(), 3);
+        assert_eq!(chain.matching("/apis").count(), 2);
+        assert_eq!(chain.matching("/").count(), 2);
+
+        assert_eq!(chain.matching("/api?x=1").count(), 3);
+        assert_eq!(chain.matching("/api/users?x=1").count(), 3);
+        assert_eq!(chain.matching("/apis?path=/api").count(), 2);
+    }
+}
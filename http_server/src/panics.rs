@@ -0,0 +1,83 @@
+use backtrace::Backtrace;
+use pool::panic_message;
+use std::cell::{Cell, RefCell};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Once;
+
+thread_local! {
+    /// Whether a panic on this thread is caught by `catch`, which then reports it
+    static CATCHING: Cell<bool> = Cell::new(false);
+    /// Location and backtrace of the last panic caught on this thread
+    static CAUGHT: RefCell<Option<(String, Backtrace)>> = RefCell::new(None);
+}
+
+static HOOK: Once = Once::new();
+
+/// A panic caught while handling a request
+pub(crate) struct CaughtPanic {
+    pub message: String,
+    /// File and line the panic started at
+    pub location: String,
+    pub backtrace: Backtrace,
+}
+
+/// Run `f`, catching a panic instead of letting it unwind the worker
+///
+/// The default panic output is skipped for panics caught here, they are reported through the
+/// returned `CaughtPanic` so that they can be logged with the request they happened in.
+pub(crate) fn catch<R>(f: impl FnOnce() -> R) -> Result<R, CaughtPanic> {
+    HOOK.call_once(install_hook);
+
+    let outer = CATCHING.with(|catching| catching.replace(true));
+    // Whatever `f` left half done is dropped with the request, nothing of it is used afterwards
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    CATCHING.with(|catching| catching.set(outer));
+
+    result.map_err(|payload| {
+        let (location, backtrace) = CAUGHT
+            .with(|caught| caught.borrow_mut().take())
+            .unwrap_or_else(|| ("unknown".to_string(), Backtrace::new()));
+
+        CaughtPanic {
+            message: panic_message(&*payload),
+            location,
+            backtrace,
+        }
+    })
+}
+
+/// Record the backtrace of panics that are caught, other panics go to the previous hook
+fn install_hook() {
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if !CATCHING.with(Cell::get) {
+            return previous(info);
+        }
+
+        let location = info.location().map_or_else(
+            || "unknown".to_string(),
+            |location| format!("{}:{}", location.file(), location.line()),
+        );
+        CAUGHT.with(|caught| *caught.borrow_mut() = Some((location, Backtrace::new())));
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn caught_panics() {
+        assert_eq!(catch(|| 42).ok(), Some(42));
+
+        let caught = catch(|| -> () { panic!("endpoint failed: {}", 42) })
+            .err()
+            .unwrap();
+        assert_eq!(caught.message, "endpoint failed: 42");
+        assert!(caught.location.starts_with(file!()));
+        assert!(!caught.backtrace.frames().is_empty());
+
+        // The thread can still panic normally afterwards
+        assert!(panic::catch_unwind(|| panic!("not caught")).is_err());
+    }
+}
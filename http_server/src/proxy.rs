@@ -0,0 +1,708 @@
+use crate::body::{Decoder, Framing, RequestBody};
+use crate::request_id::REQUEST_ID_HEADER;
+use crate::HttpRouteInfo;
+use http::{Headers, HttpVersion, RequestType, ResponseBuilder};
+use router::{Endpoint, RoutedInfo};
+use std::convert::TryFrom;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Headers that only apply to a single connection, they are not forwarded (RFC 7230 section 6.1)
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Headers saying where a request comes from, only kept when set by a trusted proxy
+const FORWARDED: &[&str] = &["x-forwarded-for", "x-forwarded-proto", "x-forwarded-host"];
+
+/// Most bytes of status line and headers accepted in an upstream response
+const MAX_HEAD_SIZE: u64 = 64 * 1024;
+
+/// Most headers accepted in an upstream response
+const MAX_HEADERS: usize = 100;
+
+/// Idle connections to the upstream older than this are closed instead of reused
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Size of the buffer bodies are copied through
+const BUFFER_SIZE: usize = 16 * 1024;
+
+/// Endpoint forwarding requests to an upstream HTTP/1.1 server, such as an app on a local port
+///
+/// The part of the path past the route the proxy is added at is forwarded, e.g. with the proxy
+/// added at "/app", "/app/users?page=2" is forwarded as "/users?page=2". The client's Host header
+/// is kept, and the upstream is told where the request came from with the X-Forwarded-For,
+/// X-Forwarded-Proto and X-Forwarded-Host headers.
+///
+/// Request and response bodies are streamed, and connections to the upstream are reused between
+/// requests. The client gets a 502 if the upstream can't be reached or sends an invalid response,
+/// and a 504 if it does not answer in time.
+pub struct Proxy {
+    upstream: SocketAddr,
+    connect_timeout: Duration,
+    read_timeout: Duration,
+    max_idle: usize,
+    /// Connections to the upstream that can be reused, the most recently used last
+    idle: Mutex<Vec<Upstream>>,
+}
+
+impl Proxy {
+    pub fn new(upstream: SocketAddr) -> Self {
+        Self {
+            upstream,
+            connect_timeout: Duration::from_secs(5),
+            read_timeout: Duration::from_secs(30),
+            max_idle: 8,
+            idle: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// How long connecting to the upstream can take, 5 seconds by default
+    pub fn connect_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// How long the upstream has to take each part of the request and to send each part of the
+    /// response, 30 seconds by default
+    pub fn read_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.read_timeout = timeout;
+        self
+    }
+
+    /// Most idle connections kept open to the upstream, 8 by default
+    pub fn max_idle(&mut self, max_idle: usize) -> &mut Self {
+        self.max_idle = max_idle;
+        self
+    }
+
+    /// Send the request upstream and its response to the client
+    fn forward(&self, info: &mut HttpRouteInfo, path: &str) -> Result<(), ProxyError> {
+        let head = request_head(info, path);
+        let chunked = info.body.content_length().is_none();
+        // A request can only be sent again if it has no body that was already read
+        let resendable = info.body.content_length() == Some(0);
+
+        let mut upstream = self.connection()?;
+        let response = loop {
+            let answered = upstream
+                .send(&head, &mut info.body, chunked)
+                .and_then(|()| upstream.read_head());
+
+            match answered {
+                Ok(response) => break response,
+                // The upstream may have closed an idle connection just as it was reused
+                Err(ProxyError::Closed) if upstream.reused && resendable => {
+                    upstream = self.open()?
+                }
+                Err(err) => return Err(err),
+            }
+        };
+
+        let no_body = match info.request().request_type() {
+            RequestType::HEAD => true,
+            _ => response.status == 204 || response.status == 304,
+        };
+        let framing = if no_body {
+            Framing::Length(0)
+        } else {
+            Framing::of(&response.headers, Framing::UntilClose)
+                .map_err(|err| ProxyError::InvalidResponse(err.to_string()))?
+        };
+        let reusable =
+            framing != Framing::UntilClose && persists(response.version, &response.headers);
+
+        // Chunks are passed on to clients that understand them, others get the body until the
+        // connection is closed
+        let chunked =
+            framing == Framing::Chunked && info.request().version() >= HttpVersion::HTTP_1_1;
+
+        let mut headers = response.headers;
+        remove_hop_by_hop(&mut headers);
+        if !no_body {
+            headers.remove("content-length");
+            match framing {
+                Framing::Length(length) => {
+                    headers.add("Content-Length".to_string(), length.to_string())
+                }
+                Framing::Chunked if chunked => {
+                    headers.add("Transfer-Encoding".to_string(), "chunked".to_string())
+                }
+                Framing::Chunked | Framing::UntilClose => info.writer.close(),
+            }
+        }
+
+        let mut head = format!("HTTP/1.1 {}\r\n", response.code);
+        for (name, value) in headers.iter() {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        info.writer
+            .start(head.as_bytes())
+            .map_err(ProxyError::Client)?;
+
+        let mut decoder = Decoder::new(framing);
+        let mut buf = vec![0; BUFFER_SIZE];
+        loop {
+            let read = decoder
+                .read(&mut upstream.reader, &mut buf)
+                .map_err(upstream_error)?;
+
+            if chunked {
+                write_chunk(&mut info.writer, &buf[..read]).map_err(ProxyError::Client)?;
+            } else {
+                info.writer
+                    .write_all(&buf[..read])
+                    .map_err(ProxyError::Client)?;
+            }
+
+            if read == 0 {
+                break;
+            }
+        }
+
+        if reusable && upstream.reader.buffer().is_empty() {
+            self.release(upstream);
+        }
+        Ok(())
+    }
+
+    /// An idle connection to the upstream, or a new one if there is none
+    fn connection(&self) -> Result<Upstream, ProxyError> {
+        let mut idle = self.idle.lock().unwrap();
+        while let Some(upstream) = idle.pop() {
+            if upstream.idle_since.elapsed() < IDLE_TIMEOUT && is_open(upstream.reader.get_ref()) {
+                return Ok(upstream);
+            }
+        }
+        drop(idle);
+
+        self.open()
+    }
+
+    fn open(&self) -> Result<Upstream, ProxyError> {
+        let stream =
+            TcpStream::connect_timeout(&self.upstream, self.connect_timeout).map_err(|err| {
+                match err.kind() {
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => ProxyError::Timeout,
+                    _ => ProxyError::Connect(err),
+                }
+            })?;
+
+        // The head and body of requests are written separately
+        stream.set_nodelay(true).map_err(ProxyError::Connect)?;
+        stream
+            .set_read_timeout(Some(self.read_timeout))
+            .map_err(ProxyError::Connect)?;
+        stream
+            .set_write_timeout(Some(self.read_timeout))
+            .map_err(ProxyError::Connect)?;
+
+        Ok(Upstream {
+            reader: BufReader::new(stream),
+            idle_since: Instant::now(),
+            reused: false,
+        })
+    }
+
+    /// Keep a connection whose response was read entirely, to reuse it for a later request
+    fn release(&self, mut upstream: Upstream) {
+        let mut idle = self.idle.lock().unwrap();
+        if idle.len() < self.max_idle {
+            upstream.idle_since = Instant::now();
+            upstream.reused = true;
+            idle.push(upstream);
+        }
+    }
+}
+
+impl Endpoint<HttpRouteInfo, ()> for Proxy {
+    fn use_strict_path_matching(&self) -> bool {
+        false
+    }
+
+    fn process(&self, info: RoutedInfo<HttpRouteInfo>) {
+        // The overload starts with the last part of the route, e.g. "app" for "/app/users"
+        let path = format!("/{}", info.path_overload.get(1..).unwrap_or(&[]).join("/"));
+        let mut route_info = info.data;
+
+        if let Err(err) = self.forward(&mut route_info, &path) {
+            warn!(
+                upstream:% = self.upstream,
+                path = path.as_str(),
+                error:% = err;
+                "Could not proxy the request"
+            );
+
+            // Part of the request or response may be left on either connection
+            route_info.writer.close();
+
+            // Once the response started it can only be cut short
+            let status = err
+                .response_status()
+                .filter(|_| route_info.writer.progress().bytes_sent() == 0);
+            if let Some(status) = status {
+                let mut response = ResponseBuilder::ok_200();
+                response
+                    .code(status)
+                    .header("Content-Type", "text/plain; charset=UTF-8")
+                    .body(status.as_bytes().to_vec());
+                let response = response.build();
+
+                // The client may already be gone
+                let _ = route_info
+                    .writer
+                    .respond(&response.head_bytes(), response.body());
+            }
+        }
+    }
+}
+
+#[derive(Debug, Fail)]
+enum ProxyError {
+    #[fail(display = "Could not connect to the upstream: {}", _0)]
+    Connect(io::Error),
+    #[fail(display = "The upstream closed the connection without answering")]
+    Closed,
+    #[fail(display = "Upstream connection failed: {}", _0)]
+    Upstream(io::Error),
+    #[fail(display = "The upstream did not answer in time")]
+    Timeout,
+    #[fail(display = "Invalid upstream response: {}", _0)]
+    InvalidResponse(String),
+    #[fail(display = "Client connection failed: {}", _0)]
+    Client(io::Error),
+}
+
+impl ProxyError {
+    /// Status the client is answered with, `None` if the client can't be answered
+    fn response_status(&self) -> Option<&'static str> {
+        match self {
+            ProxyError::Timeout => Some("504 Gateway Timeout"),
+            ProxyError::Client(_) => None,
+            _ => Some("502 Bad Gateway"),
+        }
+    }
+}
+
+/// Error of a read or write on a connection to the upstream
+fn upstream_error(err: io::Error) -> ProxyError {
+    match err.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => ProxyError::Timeout,
+        _ => ProxyError::Upstream(err),
+    }
+}
+
+/// Like `upstream_error`, for errors before the upstream answered, which can be due to the
+/// upstream having closed an idle connection
+fn unanswered(err: io::Error) -> ProxyError {
+    match err.kind() {
+        io::ErrorKind::BrokenPipe
+        | io::ErrorKind::ConnectionReset
+        | io::ErrorKind::ConnectionAborted => ProxyError::Closed,
+        _ => upstream_error(err),
+    }
+}
+
+/// Connection to the upstream
+struct Upstream {
+    reader: BufReader<TcpStream>,
+    idle_since: Instant,
+    /// The connection was used for an earlier request, the upstream may have closed it since
+    reused: bool,
+}
+
+/// Head of an upstream response
+struct ResponseHead {
+    version: HttpVersion,
+    status: u16,
+    /// Status code and reason, e.g. "200 OK"
+    code: String,
+    headers: Headers,
+}
+
+impl Upstream {
+    /// Send a request with its body, in chunks if `chunked`
+    fn send(
+        &mut self,
+        head: &[u8],
+        body: &mut RequestBody,
+        chunked: bool,
+    ) -> Result<(), ProxyError> {
+        let stream = self.reader.get_mut();
+        stream.write_all(head).map_err(unanswered)?;
+
+        let mut buf = vec![0; BUFFER_SIZE];
+        loop {
+            let read = body.read(&mut buf).map_err(ProxyError::Client)?;
+
+            if chunked {
+                write_chunk(stream, &buf[..read]).map_err(unanswered)?;
+            } else {
+                stream.write_all(&buf[..read]).map_err(unanswered)?;
+            }
+
+            if read == 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Read the head of the response, skipping informational responses such as `100 Continue`
+    fn read_head(&mut self) -> Result<ResponseHead, ProxyError> {
+        loop {
+            let head = read_response_head(&mut self.reader)?;
+            match head.status {
+                // Upgrade headers are not forwarded, so the upstream has no reason to switch
+                101 => {
+                    return Err(ProxyError::InvalidResponse(
+                        "unexpected switch of protocols".to_string(),
+                    ))
+                }
+                100..=199 => {}
+                _ => return Ok(head),
+            }
+        }
+    }
+}
+
+fn read_response_head(reader: &mut BufReader<TcpStream>) -> Result<ResponseHead, ProxyError> {
+    let mut reader = reader.take(MAX_HEAD_SIZE);
+
+    let mut line = String::new();
+    if reader.read_line(&mut line).map_err(unanswered)? == 0 {
+        return Err(ProxyError::Closed);
+    }
+    let (version, status, code) = parse_status_line(&line).ok_or_else(|| {
+        ProxyError::InvalidResponse(format!("invalid status line {:?}", line.trim_end()))
+    })?;
+
+    let mut headers = Headers::default();
+    loop {
+        line.clear();
+        reader.read_line(&mut line).map_err(upstream_error)?;
+        if !line.ends_with('\n') {
+            return Err(ProxyError::InvalidResponse(
+                "head too large or cut short".to_string(),
+            ));
+        }
+
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if headers.iter().count() >= MAX_HEADERS {
+            return Err(ProxyError::InvalidResponse("too many headers".to_string()));
+        }
+
+        let split = header
+            .find(':')
+            .ok_or_else(|| ProxyError::InvalidResponse(format!("invalid header {:?}", header)))?;
+        let (name, value) = header.split_at(split);
+        headers.add(name.trim().to_string(), value[1..].trim().to_string());
+    }
+
+    Ok(ResponseHead {
+        version,
+        status,
+        code,
+        headers,
+    })
+}
+
+/// Version, status code, and code with its reason of a status line such as "HTTP/1.1 200 OK"
+fn parse_status_line(line: &str) -> Option<(HttpVersion, u16, String)> {
+    if !line.ends_with('\n') {
+        return None;
+    }
+
+    let mut parts = line.trim_end().splitn(2, ' ');
+    let version = HttpVersion::try_from(parts.next()?).ok()?;
+    let code = parts.next()?.trim();
+
+    let digits = code.as_bytes().get(..3)?;
+    if version.major != 1
+        || !digits.iter().all(u8::is_ascii_digit)
+        || code.get(3..4).map_or(false, |space| space != " ")
+    {
+        return None;
+    }
+
+    Some((version, code[..3].parse().ok()?, code.to_string()))
+}
+
+/// Options of the Connection headers, lowercased
+fn connection_options(headers: &Headers) -> Vec<String> {
+    headers
+        .iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("connection"))
+        .flat_map(|(_, value)| value.split(','))
+        .map(|option| option.trim().to_lowercase())
+        .filter(|option| !option.is_empty())
+        .collect()
+}
+
+/// Remove the headers for a single connection, along with the ones the Connection header names
+fn remove_hop_by_hop(headers: &mut Headers) {
+    for option in connection_options(headers) {
+        headers.remove(&option);
+    }
+    for name in HOP_BY_HOP {
+        headers.remove(name);
+    }
+}
+
+/// Whether the upstream keeps the connection open after a response
+fn persists(version: HttpVersion, headers: &Headers) -> bool {
+    let options = connection_options(headers);
+    if options.iter().any(|option| option == "close") {
+        return false;
+    }
+
+    version.persists_by_default() || options.iter().any(|option| option == "keep-alive")
+}
+
+/// Whether the upstream kept an idle connection open, it sends nothing until it gets a request
+fn is_open(stream: &TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+
+    let mut byte = [0];
+    let open = match stream.peek(&mut byte) {
+        Err(ref err) => err.kind() == io::ErrorKind::WouldBlock,
+        // Closed, or sent something it should not have
+        Ok(_) => false,
+    };
+
+    stream.set_nonblocking(false).is_ok() && open
+}
+
+/// Write `data` as a chunk of a chunked body, an empty chunk ends the body
+fn write_chunk(writer: &mut impl Write, data: &[u8]) -> io::Result<()> {
+    let mut chunk = format!("{:x}\r\n", data.len()).into_bytes();
+    chunk.extend_from_slice(data);
+    chunk.extend_from_slice(b"\r\n");
+    writer.write_all(&chunk)
+}
+
+/// Head of the request sent upstream for the client's request
+fn request_head(info: &HttpRouteInfo, path: &str) -> Vec<u8> {
+    let request = info.request();
+    let mut headers = request.headers().clone();
+    remove_hop_by_hop(&mut headers);
+    // The body is sent as it is read, the upstream is not asked whether it wants it
+    headers.remove("content-length");
+    headers.remove("expect");
+
+    // Only a proxy in front of the server can say where a request comes from
+    if !info.trusted_client {
+        for name in FORWARDED {
+            headers.remove(name);
+        }
+    }
+    if let Some(ip) = info.client_ip() {
+        let forwarded_for = match headers.get("x-forwarded-for") {
+            Some(previous) => format!("{}, {}", previous, ip),
+            None => ip.to_string(),
+        };
+        headers.set("X-Forwarded-For", &forwarded_for);
+    }
+    // TLS is not supported by the server, it can only be terminated in front of it
+    if headers.get("x-forwarded-proto").is_none() {
+        headers.set("X-Forwarded-Proto", "http");
+    }
+
+    let host = request
+        .headers()
+        .get("host")
+        .unwrap_or_else(|| request.host())
+        .to_string();
+    if headers.get("x-forwarded-host").is_none() {
+        headers.set("X-Forwarded-Host", &host);
+    }
+    // HTTP/1.0 clients may not send a Host header, HTTP/1.1 requires it
+    if headers.get("host").is_none() {
+        headers.set("Host", &host);
+    }
+    headers.set(REQUEST_ID_HEADER, info.request_id());
+
+    match info.body.content_length() {
+        None => headers.set("Transfer-Encoding", "chunked"),
+        Some(length) if length > 0 || request.headers().get("content-length").is_some() => {
+            headers.set("Content-Length", &length.to_string())
+        }
+        Some(_) => {}
+    }
+
+    let mut head = format!("{} {} HTTP/1.1\r\n", request.request_type(), path);
+    for (name, value) in headers.iter() {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+
+    head.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::listener::Stream;
+    use crate::response_writer::{KeepAlive, ResponseWriter};
+    use crate::Workers;
+    use http::RequestBuilder;
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::{Arc, Weak};
+    use std::thread;
+
+    /// Stand-in upstream answering each request with its head and body, in chunks
+    /// Returns its address and the number of connections it accepted
+    fn upstream() -> (SocketAddr, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connections = Arc::new(AtomicUsize::new(0));
+
+        let accepted = connections.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                accepted.fetch_add(1, Ordering::SeqCst);
+                let stream = stream.unwrap();
+                thread::spawn(move || echo(stream));
+            }
+        });
+
+        (addr, connections)
+    }
+
+    fn echo(mut stream: TcpStream) {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        loop {
+            let mut request = String::new();
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    return;
+                }
+                if line == "\r\n" {
+                    break;
+                }
+                request.push_str(&line);
+            }
+
+            let length = request
+                .lines()
+                .find(|line| line.starts_with("Content-Length: "))
+                .map_or(0, |line| line["Content-Length: ".len()..].parse().unwrap());
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            request.push_str(&String::from_utf8(body).unwrap());
+
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nConnection: keep-alive\r\nTransfer-Encoding: chunked\r\n\r\n")
+                .unwrap();
+            write_chunk(&mut stream, request.as_bytes()).unwrap();
+            write_chunk(&mut stream, b"").unwrap();
+        }
+    }
+
+    /// Have `proxy` handle a request, returning the response the client got
+    fn proxied(proxy: &Proxy, request_type: RequestType, path: &str, body: &[u8]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        client.write_all(body).unwrap();
+
+        let mut request = RequestBuilder::new(request_type, "example.com");
+        request.path(path).header("Host", "example.com:8080");
+        if !body.is_empty() {
+            request.header("Content-Length", &body.len().to_string());
+        }
+
+        let (body, _) = RequestBody::new(
+            BufReader::new(Stream::Tcp(server.try_clone().unwrap())),
+            Framing::Length(body.len() as u64),
+            Duration::from_secs(5),
+            false,
+        );
+        let writer = ResponseWriter::new(
+            Stream::Tcp(server),
+            KeepAlive {
+                timeout: Duration::from_secs(5),
+                remaining: 10,
+            },
+            false,
+            Arc::new(AtomicBool::new(false)),
+        );
+        let info = HttpRouteInfo {
+            request: request.build(),
+            request_id: "f3a9c1e-4a2b".to_string(),
+            writer,
+            body,
+            client_ip: Some("127.0.0.1".parse().unwrap()),
+            trusted_client: false,
+            workers: Workers::new(Weak::new()),
+        };
+
+        // As routed to a proxy added at "/app"
+        proxy.process(RoutedInfo {
+            data: info,
+            path_overload: path.split('/').skip(1).map(str::to_string).collect(),
+        });
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn forward_requests() {
+        let (addr, connections) = upstream();
+        let proxy = Proxy::new(addr);
+
+        let response = proxied(&proxy, RequestType::POST, "/app/echo?page=2", b"hello");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Transfer-Encoding: chunked\r\n"));
+        assert_eq!(response.matches("Connection: ").count(), 1);
+        assert!(response.contains("POST /echo?page=2 HTTP/1.1\r\n"));
+        assert!(response.contains("Host: example.com:8080\r\n"));
+        assert!(response.contains("X-Forwarded-For: 127.0.0.1\r\n"));
+        assert!(response.contains("X-Forwarded-Proto: http\r\n"));
+        assert!(response.contains("X-Forwarded-Host: example.com:8080\r\n"));
+        assert!(response.contains("Content-Length: 5\r\n"));
+        assert!(response.ends_with("\r\nhello\r\n0\r\n\r\n"));
+
+        // The connection to the upstream is reused
+        let response = proxied(&proxy, RequestType::GET, "/app", b"");
+        assert!(response.contains("GET / HTTP/1.1\r\n"));
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn upstream_failures() {
+        // Nothing listens on the address once the listener is dropped
+        let closed = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let response = proxied(&Proxy::new(closed), RequestType::GET, "/app", b"");
+        assert!(response.starts_with("HTTP/1.1 502 Bad Gateway\r\n"));
+        assert!(response.contains("Connection: close\r\n"));
+
+        // Connections are accepted by the system, but never answered
+        let silent = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut proxy = Proxy::new(silent.local_addr().unwrap());
+        proxy.read_timeout(Duration::from_millis(100));
+        let response = proxied(&proxy, RequestType::GET, "/app", b"");
+        assert!(response.starts_with("HTTP/1.1 504 Gateway Timeout\r\n"));
+    }
+}
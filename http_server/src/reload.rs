@@ -0,0 +1,126 @@
+use crate::panics;
+use crate::virtual_hosts::SharedHosts;
+use crate::ReloadHook;
+use crossbeam::channel;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+/// Rebuilds the virtual hosts on its own thread, so that the event loop keeps accepting and
+/// dispatching connections while the reload hook runs, e.g. while it loads static roots
+///
+/// The thread stops once the `Reloader` is dropped.
+pub(crate) struct Reloader {
+    requests: channel::Sender<()>,
+}
+
+impl Reloader {
+    pub fn spawn(
+        hook: ReloadHook,
+        hosts: SharedHosts,
+        failed: Arc<AtomicBool>,
+    ) -> io::Result<Self> {
+        let (requests, received) = channel::unbounded();
+
+        thread::Builder::new()
+            .name("http-reload".to_string())
+            .spawn(move || {
+                while let Some(()) = received.recv() {
+                    // Reloads asked for while the previous one ran are done at once
+                    while let Some(()) = received.try_recv() {}
+                    reload(&hook, &hosts, &failed);
+                }
+            })?;
+
+        Ok(Self { requests })
+    }
+
+    /// Reload the hosts once the current reload, if any, is done
+    pub fn request(&self) {
+        self.requests.send(());
+    }
+}
+
+/// Replace the virtual hosts with the ones built by `hook`
+/// The current hosts are kept if they can't be built
+fn reload(hook: &ReloadHook, hosts: &SharedHosts, failed: &AtomicBool) {
+    match panics::catch(hook) {
+        Ok(Ok(new_hosts)) => {
+            hosts.replace(new_hosts);
+            failed.store(false, Ordering::SeqCst);
+            info!("Reloaded virtual hosts");
+        }
+        Ok(Err(err)) => {
+            failed.store(true, Ordering::SeqCst);
+            error!(error:% = err; "Could not reload, keeping the current hosts");
+        }
+        Err(panic) => {
+            failed.store(true, Ordering::SeqCst);
+            error!(
+                panic = panic.message.as_str(),
+                location = panic.location.as_str();
+                "Reload hook panicked, keeping the current hosts"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::VirtualHosts;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn keep_hosts_on_failure() {
+        let hosts = SharedHosts::new(VirtualHosts::default());
+        let failed = AtomicBool::new(false);
+
+        let strict: ReloadHook = Box::new(|| {
+            let mut hosts = VirtualHosts::default();
+            hosts.set_strict(true);
+            Ok(hosts)
+        });
+        let failing: ReloadHook = Box::new(|| Err(format_err!("missing static root")));
+        let panicking: ReloadHook = Box::new(|| panic!("broken hook"));
+
+        reload(&failing, &hosts, &failed);
+        assert!(failed.load(Ordering::SeqCst));
+        assert!(hosts.current().find("other.com").is_some());
+
+        reload(&strict, &hosts, &failed);
+        assert!(!failed.load(Ordering::SeqCst));
+        assert!(hosts.current().find("other.com").is_none());
+
+        let current = hosts.current();
+        reload(&panicking, &hosts, &failed);
+        assert!(failed.load(Ordering::SeqCst));
+        assert!(Arc::ptr_eq(&current, &hosts.current()));
+    }
+
+    #[test]
+    fn reload_in_background() {
+        let hosts = SharedHosts::new(VirtualHosts::default());
+        let (release, released) = channel::bounded(0);
+        let hook: ReloadHook = Box::new(move || {
+            released.recv();
+            let mut hosts = VirtualHosts::default();
+            hosts.set_strict(true);
+            Ok(hosts)
+        });
+        let reloader =
+            Reloader::spawn(hook, hosts.clone(), Arc::new(AtomicBool::new(false))).unwrap();
+
+        // Asking for a reload does not wait for the hook
+        reloader.request();
+        assert!(hosts.current().find("other.com").is_some());
+
+        release.send(());
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while hosts.current().find("other.com").is_some() {
+            assert!(Instant::now() < deadline, "the hosts were not reloaded");
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+}
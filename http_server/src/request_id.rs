@@ -0,0 +1,102 @@
+use std::cell::RefCell;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Header request IDs are received and sent in
+pub const REQUEST_ID_HEADER: &str = "X-Request-ID";
+
+/// Longest request ID accepted from a client
+const MAX_LENGTH: usize = 200;
+
+thread_local! {
+    /// ID of the request being handled on this thread
+    static CURRENT: RefCell<Option<String>> = RefCell::new(None);
+}
+
+/// ID of the request the calling thread is handling, e.g. to add it to log records
+/// Returns `None` outside of request handling
+pub fn current_request_id() -> Option<String> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// Generates request IDs that are unique to this process, e.g. "5f3a9c1e-0000002a"
+#[derive(Debug)]
+pub(crate) struct RequestIds {
+    /// Differs between processes, so IDs don't repeat after a restart
+    prefix: u32,
+    next: AtomicUsize,
+}
+
+impl Default for RequestIds {
+    fn default() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.subsec_nanos() ^ time.as_secs() as u32)
+            .unwrap_or(0);
+
+        Self {
+            prefix: nanos ^ process::id().rotate_left(16),
+            next: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl RequestIds {
+    pub fn generate(&self) -> String {
+        let id = self.next.fetch_add(1, Ordering::SeqCst);
+        format!("{:08x}-{:08x}", self.prefix, id)
+    }
+}
+
+/// IDs from clients are only kept if they can safely be echoed in a header and written to logs
+pub(crate) fn is_valid(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_LENGTH && id.bytes().all(|byte| byte.is_ascii_graphic())
+}
+
+/// Makes `id` the current request ID of the thread until dropped
+pub(crate) struct CurrentRequest;
+
+impl CurrentRequest {
+    pub fn enter(id: &str) -> Self {
+        CURRENT.with(|current| *current.borrow_mut() = Some(id.to_string()));
+        CurrentRequest
+    }
+}
+
+impl Drop for CurrentRequest {
+    fn drop(&mut self) {
+        CURRENT.with(|current| *current.borrow_mut() = None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generate_unique() {
+        let ids = RequestIds::default();
+        assert_ne!(ids.generate(), ids.generate());
+        assert_eq!(ids.generate().len(), 17);
+    }
+
+    #[test]
+    fn validate() {
+        assert!(is_valid("f3a9c1e-4a2b"));
+        assert!(!is_valid(""));
+        assert!(!is_valid("two words"));
+        assert!(!is_valid("line\r\nbreak"));
+        assert!(!is_valid(&"a".repeat(MAX_LENGTH + 1)));
+    }
+
+    #[test]
+    fn current() {
+        assert_eq!(current_request_id(), None);
+        {
+            let _current = CurrentRequest::enter("abc");
+            assert_eq!(current_request_id().unwrap(), "abc");
+        }
+        assert_eq!(current_request_id(), None);
+    }
+}
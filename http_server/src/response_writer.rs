@@ -0,0 +1,298 @@
+use crate::listener::Stream;
+use crate::middleware::ResponseFilters;
+use crate::request_id::REQUEST_ID_HEADER;
+use http::Response;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// State of a response that is shared between the endpoint writing it and the connection
+#[derive(Debug, Default)]
+pub(crate) struct ResponseProgress {
+    /// The connection is closed once the response is sent
+    close: AtomicBool,
+    /// Status code of the response, 0 until the status line is written
+    status: AtomicUsize,
+    /// Bytes written to the client, head included
+    bytes_sent: AtomicUsize,
+}
+
+impl ResponseProgress {
+    pub fn closes_connection(&self) -> bool {
+        self.close.load(Ordering::SeqCst)
+    }
+
+    /// Returns `None` if no response was written
+    pub fn status(&self) -> Option<u16> {
+        match self.status.load(Ordering::SeqCst) {
+            0 => None,
+            status => Some(status as u16),
+        }
+    }
+
+    pub fn bytes_sent(&self) -> usize {
+        self.bytes_sent.load(Ordering::SeqCst)
+    }
+}
+
+/// How the connection is kept alive after a response
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct KeepAlive {
+    pub timeout: Duration,
+    /// Requests that can still be made on the connection after this one
+    pub remaining: usize,
+}
+
+/// Writes a response to the client
+///
+/// Responses written through `respond` or `prerendered` get the connection headers
+/// (`Connection` and `Keep-Alive`) and the request ID header appended to their head, and go through
+/// the `after` of the middleware that ran for the request.
+/// Their body is left out in answer to a HEAD request.
+///
+/// Responses say HTTP/1.1 whatever the version of the request, a server answers with the highest
+/// version it supports within the request's major version (RFC 9110 section 6.2). HTTP/1.0
+/// clients can still read them: the connection headers follow their persistence rules, and bodies
+/// are delimited by `Content-Length` or by closing the connection, never chunked.
+#[derive(Debug)]
+pub struct ResponseWriter {
+    stream: Stream,
+    keep_alive: KeepAlive,
+    progress: Arc<ResponseProgress>,
+    /// Set when the server shuts down, responses then close their connection
+    draining: Arc<AtomicBool>,
+    request_id: Option<String>,
+    filters: Option<ResponseFilters>,
+    /// The request is a HEAD request, the response is sent without its body
+    head_only: bool,
+}
+
+impl ResponseWriter {
+    pub(crate) fn new(
+        stream: Stream,
+        keep_alive: KeepAlive,
+        close: bool,
+        draining: Arc<AtomicBool>,
+    ) -> Self {
+        let progress = ResponseProgress::default();
+        progress.close.store(close, Ordering::SeqCst);
+
+        Self {
+            stream,
+            keep_alive,
+            progress: Arc::new(progress),
+            draining,
+            request_id: None,
+            filters: None,
+            head_only: false,
+        }
+    }
+
+    /// Send `request_id` back to the client
+    pub(crate) fn set_request_id(&mut self, request_id: &str) {
+        self.request_id = Some(request_id.to_string());
+    }
+
+    /// Have `filters` post-process the response
+    pub(crate) fn set_filters(&mut self, filters: ResponseFilters) {
+        self.filters = Some(filters);
+    }
+
+    /// Leave the body out of responses, which still say how long it is
+    pub(crate) fn set_head_only(&mut self) {
+        self.head_only = true;
+    }
+
+    pub(crate) fn progress(&self) -> Arc<ResponseProgress> {
+        self.progress.clone()
+    }
+
+    /// Close the connection once the response is sent
+    /// Needs to be called before the response head is written
+    pub fn close(&mut self) {
+        self.progress.close.store(true, Ordering::SeqCst);
+    }
+
+    /// Write a response made of `head`, which is every line of the head except the last empty one, and `body`
+    pub fn respond(&mut self, head: &[u8], body: &[u8]) -> io::Result<()> {
+        if let Some(filters) = self.filters.take() {
+            if let Some(mut response) = Response::from_head(head, body.to_vec()) {
+                // The length is added back once the body is final
+                response.headers_mut().remove("Content-Length");
+                filters.apply(&mut response);
+                return self.respond(&response.head_bytes(), response.body());
+            }
+        }
+
+        self.write_all(head)?;
+        self.write_all(&format!("Content-Length: {}\r\n", body.len()).into_bytes())?;
+        self.end_head()?;
+        self.write_body(body)
+    }
+
+    /// Write a fully formed response, such as the ones generated in `static_out`
+    pub fn prerendered(&mut self, response: &[u8]) -> io::Result<()> {
+        let head_end = response.windows(4).position(|window| window == b"\r\n\r\n");
+
+        match head_end {
+            Some(index) if self.filters.is_some() => {
+                self.respond(&response[..index + 2], &response[index + 4..])
+            }
+            Some(index) => {
+                self.write_all(&response[..index + 2])?;
+                self.end_head()?;
+                self.write_body(&response[index + 4..])
+            }
+            None => self.write_all(response),
+        }
+    }
+
+    /// Write the head of a response whose body is then written to the writer, as it is produced
+    ///
+    /// The head needs to say how the body ends, with `Content-Length` or
+    /// `Transfer-Encoding: chunked`, or the connection needs to be closed with `close` beforehand.
+    /// Middleware do not see responses written this way.
+    pub fn start(&mut self, head: &[u8]) -> io::Result<()> {
+        self.write_all(head)?;
+        self.end_head()
+    }
+
+    fn write_body(&mut self, body: &[u8]) -> io::Result<()> {
+        if self.head_only {
+            Ok(())
+        } else {
+            self.write_all(body)
+        }
+    }
+
+    /// Write the connection headers and the empty line that ends the head
+    fn end_head(&mut self) -> io::Result<()> {
+        if self.draining.load(Ordering::SeqCst) {
+            self.close();
+        }
+
+        if let Some(request_id) = self.request_id.take() {
+            self.write_all(format!("{}: {}\r\n", REQUEST_ID_HEADER, request_id).as_bytes())?;
+        }
+
+        if self.progress.closes_connection() {
+            self.write_all(b"Connection: close\r\n\r\n")
+        } else {
+            self.write_all(
+                &format!(
+                    "Connection: keep-alive\r\nKeep-Alive: timeout={}, max={}\r\n\r\n",
+                    self.keep_alive.timeout.as_secs(),
+                    self.keep_alive.remaining
+                ).into_bytes(),
+            )
+        }
+    }
+}
+
+impl Write for ResponseWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // The first bytes of a response are its status line, e.g. "HTTP/1.1 200 OK"
+        if self.progress.bytes_sent() == 0 {
+            if let Some(status) = parse_status(buf) {
+                self.progress.status.store(status, Ordering::SeqCst);
+            }
+        }
+
+        let written = self.stream.write(buf)?;
+        self.progress
+            .bytes_sent
+            .fetch_add(written, Ordering::SeqCst);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+/// Status code of a status line
+fn parse_status(line: &[u8]) -> Option<usize> {
+    let code = line.split(|byte| *byte == b' ').nth(1)?;
+
+    if code.len() == 3 && code.iter().all(u8::is_ascii_digit) {
+        std::str::from_utf8(code).ok()?.parse().ok()
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::os::unix::net::UnixStream;
+
+    /// Response read back from a writer set up by `setup`, once it wrote with `write`
+    fn written(
+        setup: impl FnOnce(&mut ResponseWriter),
+        write: impl FnOnce(&mut ResponseWriter) -> io::Result<()>,
+    ) -> String {
+        let (server, mut client) = UnixStream::pair().unwrap();
+        let mut writer = ResponseWriter::new(
+            Stream::Unix(server),
+            KeepAlive {
+                timeout: Duration::from_secs(5),
+                remaining: 10,
+            },
+            true,
+            Arc::new(AtomicBool::new(false)),
+        );
+        setup(&mut writer);
+        write(&mut writer).unwrap();
+        drop(writer);
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn head_responses() {
+        let respond =
+            |writer: &mut ResponseWriter| writer.respond(b"HTTP/1.1 200 OK\r\n", b"hello");
+        assert_eq!(
+            written(|_| {}, respond),
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello"
+        );
+        assert_eq!(
+            written(ResponseWriter::set_head_only, respond),
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: close\r\n\r\n"
+        );
+
+        let prerendered = |writer: &mut ResponseWriter| {
+            writer.prerendered(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello")
+        };
+        assert_eq!(
+            written(ResponseWriter::set_head_only, prerendered),
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: close\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn close_while_draining() {
+        let respond =
+            |writer: &mut ResponseWriter| writer.respond(b"HTTP/1.1 200 OK\r\n", b"hello");
+        let persistent =
+            |writer: &mut ResponseWriter| writer.progress.close.store(false, Ordering::SeqCst);
+        assert_eq!(
+            written(persistent, respond),
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: keep-alive\r\nKeep-Alive: timeout=5, max=10\r\n\r\nhello"
+        );
+
+        // A response started before the server began draining still closes its connection
+        let draining = |writer: &mut ResponseWriter| {
+            persistent(writer);
+            writer.draining.store(true, Ordering::SeqCst);
+        };
+        assert_eq!(
+            written(draining, respond),
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello"
+        );
+    }
+}
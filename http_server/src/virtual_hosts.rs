@@ -0,0 +1,201 @@
+use crate::middleware::MiddlewareChain;
+use crate::HttpRouteInfo;
+use router::Router;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+/// Routers for every domain served, selected with the request's Host header
+///
+/// Hosts are either exact (`example.com`) or wildcards (`*.example.com`) that match any subdomain.
+/// Exact hosts are matched first, then the most specific wildcard.
+/// Requests for any other host go to the default router, or are refused with a
+/// 421 Misdirected Request when the hosts are strict.
+#[derive(Default)]
+pub struct VirtualHosts {
+    default: Host,
+    exact: HashMap<String, Host>,
+    /// Wildcard hosts by their suffix, e.g. ".example.com", the longest suffixes come first
+    wildcards: Vec<(String, Host)>,
+    strict: bool,
+}
+
+/// Router of a host and the middleware that run before it
+#[derive(Default)]
+pub(crate) struct Host {
+    pub router: Router<HttpRouteInfo, ()>,
+    pub middleware: MiddlewareChain,
+}
+
+impl VirtualHosts {
+    /// Router used for requests that don't match any host
+    pub fn default_mut(&mut self) -> &mut Router<HttpRouteInfo, ()> {
+        &mut self.default.router
+    }
+
+    /// Middleware of the default router
+    pub fn default_middleware_mut(&mut self) -> &mut MiddlewareChain {
+        &mut self.default.middleware
+    }
+
+    /// Router of a host, created if it does not exist yet
+    /// `host` is either a domain or a wildcard such as `*.example.com`
+    pub fn host_mut(&mut self, host: &str) -> &mut Router<HttpRouteInfo, ()> {
+        &mut self.host_entry(host).router
+    }
+
+    /// Middleware of a host, which run after the server's global middleware
+    /// The host is created if it does not exist yet
+    pub fn host_middleware_mut(&mut self, host: &str) -> &mut MiddlewareChain {
+        &mut self.host_entry(host).middleware
+    }
+
+    fn host_entry(&mut self, host: &str) -> &mut Host {
+        let host = normalize_host(host);
+
+        if host.starts_with("*.") {
+            let suffix = host[1..].to_string();
+
+            if !self.wildcards.iter().any(|(s, _)| *s == suffix) {
+                self.wildcards.push((suffix.clone(), Host::default()));
+                // The most specific wildcards need to be matched first
+                self.wildcards
+                    .sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()));
+            }
+
+            let index = self
+                .wildcards
+                .iter()
+                .position(|(s, _)| *s == suffix)
+                .unwrap();
+
+            &mut self.wildcards[index].1
+        } else {
+            self.exact.entry(host).or_default()
+        }
+    }
+
+    /// Strict hosts refuse requests for unknown hosts instead of using the default router
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Router for the value of a Host header
+    /// Returns `None` if the host is unknown and hosts are strict
+    pub fn find(&self, host: &str) -> Option<&Router<HttpRouteInfo, ()>> {
+        self.lookup(host).map(|host| &host.router)
+    }
+
+    pub(crate) fn lookup(&self, host: &str) -> Option<&Host> {
+        let host = normalize_host(host);
+
+        if let Some(found) = self.exact.get(&host) {
+            return Some(found);
+        }
+
+        let wildcard = self
+            .wildcards
+            .iter()
+            .find(|(suffix, _)| host.len() > suffix.len() && host.ends_with(suffix.as_str()));
+
+        match wildcard {
+            Some((_, found)) => Some(found),
+            None if self.strict => None,
+            None => Some(&self.default),
+        }
+    }
+}
+
+/// Virtual hosts that can be replaced while the server is running
+///
+/// Requests hold on to the hosts that were current when they started, so replacing them never
+/// changes the routers of an in-flight request.
+#[derive(Clone, Default)]
+pub(crate) struct SharedHosts {
+    current: Arc<RwLock<Arc<VirtualHosts>>>,
+}
+
+impl SharedHosts {
+    pub fn new(hosts: VirtualHosts) -> Self {
+        Self {
+            current: Arc::new(RwLock::new(Arc::new(hosts))),
+        }
+    }
+
+    pub fn current(&self) -> Arc<VirtualHosts> {
+        self.current.read().unwrap().clone()
+    }
+
+    pub fn replace(&self, hosts: VirtualHosts) {
+        *self.current.write().unwrap() = Arc::new(hosts);
+    }
+}
+
+/// Lower cases a host and removes its port and trailing dot
+pub(crate) fn normalize_host(host: &str) -> String {
+    let host = host.trim();
+
+    let host = if host.starts_with('[') {
+        // IPv6 literal, e.g. "[::1]:8080"
+        match host.find(']') {
+            Some(end) => &host[..=end],
+            None => host,
+        }
+    } else {
+        match host.rfind(':') {
+            Some(port_start) => &host[..port_start],
+            None => host,
+        }
+    };
+
+    host.trim_end_matches('.').to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize() {
+        assert_eq!(normalize_host("Example.COM"), "example.com");
+        assert_eq!(normalize_host("example.com:8080"), "example.com");
+        assert_eq!(normalize_host("example.com."), "example.com");
+        assert_eq!(normalize_host("[::1]:80"), "[::1]");
+        assert_eq!(normalize_host("[::1]"), "[::1]");
+    }
+
+    #[test]
+    fn find_hosts() {
+        let mut hosts = VirtualHosts::default();
+        hosts.host_mut("example.com");
+        hosts.host_mut("*.example.com");
+        hosts.host_mut("*.blog.example.com");
+
+        let exact = &hosts.exact.get("example.com").unwrap().router as *const _;
+        let wildcard = &hosts.wildcards[1].1.router as *const _;
+        let blog = &hosts.wildcards[0].1.router as *const _;
+        let default = &hosts.default.router as *const _;
+
+        assert_eq!(hosts.find("EXAMPLE.com:80").unwrap() as *const _, exact);
+        assert_eq!(hosts.find("www.example.com").unwrap() as *const _, wildcard);
+        assert_eq!(hosts.find("a.blog.example.com").unwrap() as *const _, blog);
+        assert_eq!(hosts.find("other.com").unwrap() as *const _, default);
+
+        hosts.set_strict(true);
+        assert!(hosts.find("other.com").is_none());
+        assert!(hosts.find("notexample.com").is_none());
+    }
+
+    #[test]
+    fn replace_shared_hosts() {
+        let shared = SharedHosts::new(VirtualHosts::default());
+        let before = shared.current();
+
+        let mut hosts = VirtualHosts::default();
+        hosts.set_strict(true);
+        shared.replace(hosts);
+
+        // Hosts taken before the swap are not affected by it
+        assert!(before.find("other.com").is_some());
+        assert!(shared.current().find("other.com").is_none());
+    }
+}
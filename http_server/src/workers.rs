@@ -0,0 +1,163 @@
+use crate::connection::Connection;
+use crate::{HttpServerError, InFlight, ServerState};
+use pool::{ScheduleHandle, Task, TaskHandle, ThreadPool, Work};
+use std::fmt::{self, Debug};
+use std::panic::{RefUnwindSafe, UnwindSafe};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+/// Pool of the workers serving connections
+pub(crate) type ServerPool = ThreadPool<Arc<ServerState>, ServerWork>;
+
+/// What the server's workers do
+pub(crate) enum ServerWork {
+    /// Serve a connection that has a request ready to be read
+    Serve(Connection),
+    /// Work handed to the workers through `Workers`
+    Task(Task<Arc<ServerState>>),
+}
+
+impl Work<Arc<ServerState>> for ServerWork {
+    fn run(self, state: &Arc<ServerState>) {
+        match self {
+            ServerWork::Serve(connection) => {
+                let _in_flight = InFlight(&state.in_flight);
+
+                if let Err(err) = connection.serve(state) {
+                    warn!(error:% = err; "Error in request");
+                }
+            }
+            ServerWork::Task(task) => task.run(state),
+        }
+    }
+}
+
+impl From<Task<Arc<ServerState>>> for ServerWork {
+    fn from(task: Task<Arc<ServerState>>) -> Self {
+        ServerWork::Task(task)
+    }
+}
+
+/// Runs work on the workers that serve connections, e.g. to move CPU-bound work of an endpoint
+/// off its connection's worker
+///
+/// Work waits in the same queue as connections. An endpoint waiting on a handle holds its own
+/// worker meanwhile, so the work can only run if another worker is free or can be added.
+#[derive(Clone)]
+pub struct Workers {
+    pool: Weak<ServerPool>,
+}
+
+impl Workers {
+    pub(crate) fn new(pool: Weak<ServerPool>) -> Self {
+        Self { pool }
+    }
+
+    /// Run `work` on a worker and get a handle to wait for its result
+    /// Fails once the server stopped its workers
+    pub fn submit<F, R>(&self, work: F) -> Result<TaskHandle<R>, HttpServerError>
+    where
+        F: FnOnce() -> R + Send + UnwindSafe + 'static,
+        R: Send + 'static,
+    {
+        Ok(self.pool()?.submit(move |_: &Arc<ServerState>| work()))
+    }
+
+    /// Run `task` on a worker once `delay` has passed
+    /// Tasks that are not due yet when the server shuts down are cancelled
+    pub fn schedule_after(
+        &self,
+        delay: Duration,
+        task: impl FnOnce() + Send + UnwindSafe + 'static,
+    ) -> Result<ScheduleHandle, HttpServerError> {
+        Ok(self
+            .pool()?
+            .schedule_after(delay, move |_: &Arc<ServerState>| task()))
+    }
+
+    /// Run `task` on a worker every `interval`, e.g. to warm a cache or flush stats
+    /// A run is skipped if the previous one is still going, the task stops when the server
+    /// shuts down
+    pub fn schedule_every(
+        &self,
+        interval: Duration,
+        task: impl Fn() + Send + Sync + RefUnwindSafe + 'static,
+    ) -> Result<ScheduleHandle, HttpServerError> {
+        Ok(self
+            .pool()?
+            .schedule_every(interval, move |_: &Arc<ServerState>| task()))
+    }
+
+    fn pool(&self) -> Result<Arc<ServerPool>, HttpServerError> {
+        self.pool.upgrade().ok_or(HttpServerError::WorkersStopped)
+    }
+}
+
+impl Debug for Workers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Workers")
+            .field("running", &self.pool.upgrade().is_some())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HttpRouteInfo, Stream, VirtualHosts};
+    use crossbeam::channel;
+    use router::{Endpoint, RoutedInfo};
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+    use std::sync::atomic::Ordering;
+
+    /// Sums numbers on another worker and responds with the sum
+    struct Offload;
+
+    impl Endpoint<HttpRouteInfo, ()> for Offload {
+        fn process(&self, info: RoutedInfo<HttpRouteInfo>) {
+            let mut info = info.data;
+            let sum = info.workers().submit(|| (1..=100).sum::<u32>()).unwrap();
+            let sum = sum.wait().unwrap().to_string();
+            let _ = info
+                .writer()
+                .respond(b"HTTP/1.1 200 OK\r\n", sum.as_bytes());
+        }
+    }
+
+    #[test]
+    fn offload_from_endpoint() {
+        let mut hosts = VirtualHosts::default();
+        hosts.default_mut().add_path("/sum", Offload);
+        let state = Arc::new(ServerState::for_tests(hosts));
+        let pool = Arc::new(ServerPool::new(2, state.clone()));
+        *state.workers.write().unwrap() = Workers::new(Arc::downgrade(&pool));
+
+        let (server, mut client) = UnixStream::pair().unwrap();
+        client
+            .write_all(b"GET /sum HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let connection = Connection::new(Stream::Unix(server), &state.metrics, None);
+        state.in_flight.fetch_add(1, Ordering::SeqCst);
+        pool.do_work(ServerWork::Serve(connection));
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\n5050"));
+
+        let (sender, receiver) = channel::bounded(1);
+        state
+            .workers()
+            .schedule_after(Duration::from_millis(10), move || sender.send(()))
+            .unwrap();
+        receiver.recv().unwrap();
+
+        match Arc::try_unwrap(pool) {
+            Ok(pool) => assert_eq!(pool.join().unwrap().len(), 2),
+            Err(_) => panic!("the pool is only held by the test"),
+        }
+        assert_eq!(state.in_flight.load(Ordering::SeqCst), 0);
+        assert!(state.workers().submit(|| ()).is_err());
+    }
+}
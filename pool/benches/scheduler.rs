@@ -0,0 +1,92 @@
+//! Compares the shared channel with work stealing, at the 40 workers and queue of 1024 the server
+//! uses by default.
+//!
+//! Work is sent from a single thread, as the server's event loop does, and spins for a fixed time.
+//! Latency is measured from the moment work is sent to the moment it is done.
+//!
+//! Run with `cargo bench -p pool`
+
+use pool::{ThreadPool, ThreadPoolBuilder};
+use std::panic::UnwindSafe;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const WORKERS: usize = 40;
+const MAX_QUEUED: usize = 1024;
+
+/// Microseconds each piece of work spins for, with the number of pieces of work sent
+const LOADS: &[(u64, usize)] = &[(0, 200_000), (10, 50_000), (100, 10_000)];
+
+/// Nanoseconds between sending work and it being done, by work index
+type Latencies = Arc<Vec<AtomicUsize>>;
+
+fn work(
+    index: usize,
+    cost: Duration,
+    start: Instant,
+) -> impl FnOnce(&Latencies) + Send + UnwindSafe + 'static {
+    let sent = start.elapsed();
+    move |latencies: &Latencies| {
+        let began = Instant::now();
+        while began.elapsed() < cost {}
+
+        let latency = start.elapsed() - sent;
+        latencies[index].store(nanos(latency), Ordering::SeqCst);
+    }
+}
+
+fn nanos(duration: Duration) -> usize {
+    duration.as_secs() as usize * 1_000_000_000 + duration.subsec_nanos() as usize
+}
+
+fn run(name: &str, stealing: bool, micros: u64, jobs: usize) {
+    let cost = Duration::from_micros(micros);
+    let latencies: Latencies = Arc::new((0..jobs).map(|_| AtomicUsize::new(0)).collect());
+
+    let mut builder = ThreadPoolBuilder::new(WORKERS);
+    builder.max_queued(MAX_QUEUED);
+    if stealing {
+        builder.work_stealing();
+    }
+    let pool: ThreadPool<Latencies, _> = builder.build(latencies.clone());
+
+    let start = Instant::now();
+    for index in 0..jobs {
+        pool.do_work(work(index, cost, start));
+    }
+    pool.join().unwrap();
+    let elapsed = start.elapsed();
+
+    let mut latencies: Vec<usize> = latencies
+        .iter()
+        .map(|latency| latency.load(Ordering::SeqCst))
+        .collect();
+    latencies.sort();
+    let percentile = |p: f64| {
+        let index = ((latencies.len() - 1) as f64 * p) as usize;
+        latencies[index] as f64 / 1000.0
+    };
+
+    println!(
+        "{:<8} {:>6} {:>12.0} {:>12.1} {:>12.1} {:>12.1} {:>12.1}",
+        name,
+        format!("{}us", micros),
+        jobs as f64 / (elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9),
+        percentile(0.5),
+        percentile(0.99),
+        percentile(0.999),
+        percentile(1.0),
+    );
+}
+
+fn main() {
+    println!(
+        "{:<8} {:>6} {:>12} {:>12} {:>12} {:>12} {:>12}",
+        "", "work", "jobs/s", "p50 (us)", "p99 (us)", "p99.9 (us)", "max (us)"
+    );
+    for &(micros, jobs) in LOADS {
+        run("shared", false, micros, jobs);
+        run("stealing", true, micros, jobs);
+    }
+}
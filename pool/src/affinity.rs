@@ -0,0 +1,33 @@
+use std::io;
+
+/// Pin the calling thread to `cpu`, so that the scheduler only runs it there
+#[cfg(target_os = "linux")]
+pub fn pin_current_thread(cpu: usize) -> io::Result<()> {
+    use std::mem;
+
+    unsafe {
+        let mut set: libc::cpu_set_t = mem::zeroed();
+        if cpu >= 8 * mem::size_of_val(&set) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("no CPU {}", cpu),
+            ));
+        }
+        libc::CPU_SET(cpu, &mut set);
+
+        // 0 is the calling thread
+        if libc::sched_setaffinity(0, mem::size_of_val(&set), &set) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pin_current_thread(_cpu: usize) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "pinning threads to CPUs is only supported on Linux",
+    ))
+}
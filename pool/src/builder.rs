@@ -0,0 +1,125 @@
+use super::worker::ThreadOptions;
+use super::{ThreadPool, Work};
+use std::panic::{RefUnwindSafe, UnwindSafe};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Configures a ThreadPool and the threads of its workers
+///
+/// ```
+/// # use pool::{ThreadPool, ThreadPoolBuilder};
+/// let pool: ThreadPool<(), fn(&())> = ThreadPoolBuilder::new(4)
+///     .max_queued(1024)
+///     .name_prefix("worker")
+///     .build(());
+/// # pool.join().unwrap();
+/// ```
+#[derive(Clone)]
+pub struct ThreadPoolBuilder {
+    pub(crate) workers: usize,
+    pub(crate) max_queued: Option<usize>,
+    /// Most workers and how long the ones above `workers` stay idle before retiring
+    pub(crate) elastic: Option<(usize, Duration)>,
+    pub(crate) work_stealing: bool,
+    pub(crate) threads: ThreadOptions,
+}
+
+impl ThreadPoolBuilder {
+    /// A pool of `workers` worker threads, with an unbounded queue
+    pub fn new(workers: usize) -> Self {
+        Self {
+            workers,
+            max_queued: None,
+            elastic: None,
+            work_stealing: false,
+            threads: ThreadOptions::default(),
+        }
+    }
+
+    /// Queue at most `max_queued` pieces of work, as `ThreadPool::bounded` does
+    pub fn max_queued(&mut self, max_queued: usize) -> &mut Self {
+        self.max_queued = Some(max_queued);
+        self
+    }
+
+    /// Add workers while work waits for one, up to `max`, as `ThreadPool::elastic` does
+    pub fn elastic(&mut self, max: usize, idle_timeout: Duration) -> &mut Self {
+        self.elastic = Some((max, idle_timeout));
+        self
+    }
+
+    /// Give every worker its own queue, filled in batches from the shared one while the pool is
+    /// saturated, and that idle workers steal from
+    pub fn work_stealing(&mut self) -> &mut Self {
+        self.work_stealing = true;
+        self
+    }
+
+    /// Name the threads "<prefix>-<worker id>", as shown by `top -H`, `perf` or panic messages
+    pub fn name_prefix(&mut self, prefix: &str) -> &mut Self {
+        self.threads.name_prefix = Some(prefix.to_string());
+        self
+    }
+
+    /// Stack size of the threads, in bytes
+    pub fn stack_size(&mut self, bytes: usize) -> &mut Self {
+        self.threads.stack_size = Some(bytes);
+        self
+    }
+
+    /// Pin each worker to one of `cpus`, in turn
+    /// Only supported on Linux, elsewhere a warning is logged and workers run on any CPU
+    pub fn pin_to_cpus(&mut self, cpus: &[usize]) -> &mut Self {
+        self.threads.cpus = cpus.to_vec();
+        self
+    }
+
+    /// Call `init` on every worker thread before it takes any work, e.g. to set up thread locals
+    /// `init` is given the id of the worker
+    pub fn on_start(&mut self, init: impl Fn(usize) + Send + Sync + 'static) -> &mut Self {
+        self.threads.init = Some(Arc::new(init));
+        self
+    }
+
+    pub fn build<S, T>(&self, state: S) -> ThreadPool<S, T>
+    where
+        S: Clone + RefUnwindSafe + UnwindSafe + Send + Sync + 'static,
+        T: Work<S>,
+    {
+        ThreadPool::create(self, state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Task;
+    use std::cell::Cell;
+    use std::thread;
+
+    thread_local! {
+        static WORKER_ID: Cell<Option<usize>> = Cell::new(None);
+    }
+
+    #[test]
+    fn thread_options() {
+        let pool: ThreadPool<(), Task<()>> = ThreadPoolBuilder::new(2)
+            .name_prefix("test-worker")
+            .stack_size(256 * 1024)
+            .on_start(|id| WORKER_ID.with(|worker| worker.set(Some(id))))
+            .build(());
+
+        let seen = pool.submit(|_: &()| {
+            (
+                thread::current().name().map(str::to_string),
+                WORKER_ID.with(Cell::get),
+            )
+        });
+        let (name, id) = seen.wait().unwrap();
+        let id = id.unwrap();
+        assert!(id < 2);
+        assert_eq!(name, Some(format!("test-worker-{}", id)));
+
+        pool.join().unwrap();
+    }
+}
@@ -1,12 +1,45 @@
+mod affinity;
+mod builder;
+mod schedule;
+mod stealing;
+mod task;
 mod worker;
 
 #[macro_use]
 extern crate failure;
+#[macro_use]
+extern crate log;
 extern crate core;
 
-use self::worker::{Worker, WorkerMessage, WorkerResult};
+pub use self::builder::ThreadPoolBuilder;
+pub use self::schedule::ScheduleHandle;
+use self::schedule::Timer;
+use self::stealing::Stealers;
+pub use self::task::{panic_message, Panicked, Task, TaskHandle};
+pub use self::worker::WorkerResult;
+use self::worker::{Retire, ThreadOptions, Worker, WorkerMessage};
 use crossbeam as channel;
+use std::cell::Cell;
+use std::collections::BTreeMap;
 use std::panic::{RefUnwindSafe, UnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Something a worker can do with the state of its pool
+/// Closures taking the state are work, `Task` is work of any type
+pub trait Work<S>: Send + UnwindSafe + 'static {
+    fn run(self, state: &S);
+}
+
+impl<S, F> Work<S> for F
+where
+    F: FnOnce(&S) + Send + UnwindSafe + 'static,
+{
+    fn run(self, state: &S) {
+        self(state)
+    }
+}
 
 #[derive(Debug, Fail)]
 pub enum PoolError {
@@ -15,47 +48,327 @@ pub enum PoolError {
 }
 
 /// Takes care of sending work to worker threads
+///
+/// Elastic pools start with their minimum number of workers, spawn more while work waits for a
+/// worker, up to their maximum, and retire workers that have been idle for a while.
 pub struct ThreadPool<S, T>
 where
-    T: FnOnce(&S) + Send + 'static + UnwindSafe,
+    T: Work<S>,
     S: RefUnwindSafe + UnwindSafe + Send + Sync + 'static,
 {
-    workers: Vec<Worker<S, T>>,
+    /// Workers by id, retired workers are joined and removed when the pool grows
+    workers: Mutex<BTreeMap<usize, Worker<S, T>>>,
+    next_id: AtomicUsize,
     sender: channel::Sender<WorkerMessage<T>>,
+    receiver: channel::Receiver<WorkerMessage<T>>,
+    /// `None` for pools with an unbounded queue
+    max_queued: Option<usize>,
+    state: S,
+    stats: Arc<PoolStats>,
+    /// `None` for pools with a fixed number of workers
+    elastic: Option<Elastic>,
+    threads: ThreadOptions,
+    /// Queues of the workers, `None` unless the pool steals work
+    stealers: Option<Arc<Stealers<T>>>,
+    /// Started once a task is scheduled
+    timer: Mutex<Option<Timer<T>>>,
+}
+
+/// Bounds of an elastic pool
+struct Elastic {
+    max: usize,
+    retire: Retire,
+    /// Ids of the workers that retired
+    retired: channel::Receiver<usize>,
+}
+
+/// Live counters of a pool, shared with its workers
+#[derive(Debug, Default)]
+pub struct PoolStats {
+    /// Work sent to the pool that no worker has started yet, work in the workers' own queues
+    /// included
+    queued: AtomicUsize,
+    /// Work that panicked since the pool was created
+    panics: AtomicUsize,
+    /// Work that panicked on a worker, by worker id, retired workers included
+    worker_panics: Mutex<BTreeMap<usize, usize>>,
+    /// Workers running, busy or not
+    workers: AtomicUsize,
+    /// Workers doing work
+    busy: AtomicUsize,
+}
+
+impl PoolStats {
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::SeqCst)
+    }
+
+    pub fn panics(&self) -> usize {
+        self.panics.load(Ordering::SeqCst)
+    }
+
+    /// Work that panicked on each worker that had a panic, by worker id
+    pub fn worker_panics(&self) -> BTreeMap<usize, usize> {
+        self.worker_panics.lock().unwrap().clone()
+    }
+
+    fn worker_panicked(&self, id: usize) {
+        self.panics.fetch_add(1, Ordering::SeqCst);
+        *self.worker_panics.lock().unwrap().entry(id).or_insert(0) += 1;
+    }
+
+    pub fn workers(&self) -> usize {
+        self.workers.load(Ordering::SeqCst)
+    }
+
+    pub fn busy(&self) -> usize {
+        self.busy.load(Ordering::SeqCst)
+    }
+
+    /// Share of the workers that are busy, from 0 to 1
+    pub fn utilization(&self) -> f64 {
+        match self.workers() {
+            0 => 0.0,
+            workers => self.busy() as f64 / workers as f64,
+        }
+    }
+
+    /// Count one more worker, unless there are `max` already
+    fn try_grow(&self, max: usize) -> bool {
+        let mut workers = self.workers();
+        while workers < max {
+            match self.workers.compare_exchange(
+                workers,
+                workers + 1,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            ) {
+                Ok(_) => return true,
+                Err(current) => workers = current,
+            }
+        }
+        false
+    }
+
+    /// Count one less worker, unless there are only `min` left
+    fn try_shrink(&self, min: usize) -> bool {
+        let mut workers = self.workers();
+        while workers > min {
+            match self.workers.compare_exchange(
+                workers,
+                workers - 1,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            ) {
+                Ok(_) => return true,
+                Err(current) => workers = current,
+            }
+        }
+        false
+    }
 }
 
 impl<S, T> ThreadPool<S, T>
 where
     S: Clone + RefUnwindSafe + UnwindSafe + Send + Sync + 'static,
-    T: FnOnce(&S) + Send + 'static + UnwindSafe,
+    T: Work<S>,
 {
     /// Create a new ThreadPool
     /// `worker_num` number of worker threads created
     pub fn new(worker_num: usize, state: S) -> Self {
-        let mut workers = Vec::with_capacity(worker_num);
+        ThreadPoolBuilder::new(worker_num).build(state)
+    }
+
+    /// Create a ThreadPool that queues at most `max_queued` pieces of work
+    /// `do_work` then waits for room in the queue, `try_do_work` gives the work back
+    pub fn bounded(worker_num: usize, max_queued: usize, state: S) -> Self {
+        ThreadPoolBuilder::new(worker_num)
+            .max_queued(max_queued)
+            .build(state)
+    }
+
+    /// Create a ThreadPool of `min` to `max` workers, workers above `min` retire once they have
+    /// been idle for `idle_timeout`
+    /// At most `max_queued` pieces of work are queued, as with `bounded`
+    pub fn elastic(
+        min: usize,
+        max: usize,
+        idle_timeout: Duration,
+        max_queued: usize,
+        state: S,
+    ) -> Self {
+        ThreadPoolBuilder::new(min)
+            .max_queued(max_queued)
+            .elastic(max, idle_timeout)
+            .build(state)
+    }
 
-        let (sender, receiver) = channel::unbounded();
+    fn create(builder: &ThreadPoolBuilder, state: S) -> Self {
+        let (sender, receiver) = match builder.max_queued {
+            Some(max_queued) => channel::bounded(max_queued),
+            None => channel::unbounded(),
+        };
 
+        let worker_num = builder.workers;
+        let elastic = builder.elastic.map(|(max, idle_timeout)| {
+            let (retired_sender, retired) = channel::unbounded();
+            Elastic {
+                max: max.max(worker_num),
+                retire: Retire {
+                    min: worker_num,
+                    idle_timeout,
+                    retired: retired_sender,
+                },
+                retired,
+            }
+        });
+
+        let pool = Self {
+            workers: Mutex::default(),
+            next_id: AtomicUsize::new(0),
+            sender,
+            receiver,
+            max_queued: builder.max_queued,
+            state,
+            stats: Arc::new(PoolStats::default()),
+            elastic,
+            threads: builder.threads.clone(),
+            stealers: if builder.work_stealing {
+                Some(Arc::default())
+            } else {
+                None
+            },
+            timer: Mutex::default(),
+        };
+
+        pool.stats.workers.store(worker_num, Ordering::SeqCst);
         for _ in 0..worker_num {
-            workers.push(Worker::spawn(receiver.clone(), state.clone()));
+            pool.spawn();
         }
 
-        Self { workers, sender }
+        pool
+    }
+
+    fn spawn(&self) {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let retire = self.elastic.as_ref().map(|elastic| elastic.retire.clone());
+
+        let worker = Worker::spawn(
+            id,
+            self.receiver.clone(),
+            self.state.clone(),
+            self.stats.clone(),
+            retire,
+            &self.threads,
+            self.stealers.clone(),
+        );
+        self.workers.lock().unwrap().insert(id, worker);
+    }
+
+    /// Call `f` with the timer of scheduled tasks, which is started on first use
+    fn timer<R>(&self, f: impl FnOnce(&mut Timer<T>) -> R) -> R {
+        let mut timer = self.timer.lock().unwrap();
+        let timer = timer.get_or_insert_with(|| {
+            let name = self
+                .threads
+                .name_prefix
+                .as_ref()
+                .map(|prefix| format!("{}-timer", prefix));
+            Timer::start(name, self.sender.clone(), self.stats.clone())
+        });
+        f(timer)
+    }
+
+    /// Spawn a worker if the work about to be queued would otherwise wait
+    fn grow(&self) {
+        let elastic = match &self.elastic {
+            Some(elastic) => elastic,
+            None => return,
+        };
+
+        let idle = self.stats.workers().saturating_sub(self.stats.busy());
+        if self.stats.queued() < idle || !self.stats.try_grow(elastic.max) {
+            return;
+        }
+
+        // Retired workers are done, joining them frees their thread
+        while let Some(id) = elastic.retired.try_recv() {
+            let worker = self.workers.lock().unwrap().remove(&id);
+            if let Some(worker) = worker {
+                let _ = worker.join();
+            }
+        }
+
+        self.spawn();
     }
 
     /// Send work to a worker thread
     pub fn do_work(&self, work: T) {
+        self.grow();
+        self.stats.queued.fetch_add(1, Ordering::SeqCst);
         self.sender.send(WorkerMessage::Work(work));
     }
 
+    /// Send work to a worker thread unless the queue is full
+    /// Returns the work if it could not be queued
+    pub fn try_do_work(&self, work: T) -> Result<(), T> {
+        self.grow();
+        // Work that workers took in a batch has left the channel, but is still queued
+        if self
+            .max_queued
+            .is_some_and(|max| self.stats.queued() >= max)
+        {
+            return Err(work);
+        }
+        // Counted first, a worker may take the work before this returns
+        self.stats.queued.fetch_add(1, Ordering::SeqCst);
+
+        // The work is only taken out if the send happens
+        let work = Cell::new(Some(work));
+        let sent = channel::Select::new()
+            .send(
+                &self.sender,
+                || WorkerMessage::Work(work.take().unwrap()),
+                || true,
+            ).default(|| false)
+            .wait();
+
+        if sent {
+            Ok(())
+        } else {
+            self.stats.queued.fetch_sub(1, Ordering::SeqCst);
+            Err(work.into_inner().unwrap())
+        }
+    }
+
+    /// Counters that stay readable after the pool is moved or joined
+    pub fn stats(&self) -> Arc<PoolStats> {
+        self.stats.clone()
+    }
+
+    /// Cancel every scheduled task and stop the thread that waits for them to be due
+    /// Work of tasks that were already due is still done
+    pub fn cancel_scheduled(&self) {
+        if let Some(timer) = self.timer.lock().unwrap().take() {
+            timer.stop();
+        }
+    }
+
+    /// Wait for the work sent so far to be done and stop the workers
+    /// Scheduled tasks that are not running yet are cancelled
     pub fn join(self) -> Result<Vec<WorkerResult>, PoolError> {
-        for _ in 0..self.workers.len() {
+        self.cancel_scheduled();
+
+        // Workers that retire in the meantime leave a message behind, which is harmless
+        for _ in 0..self.stats.workers() {
             self.sender.send(WorkerMessage::Resign);
         }
 
         self.workers
+            .into_inner()
+            .unwrap()
             .into_iter()
-            .map(|worker| worker.join())
+            .map(|(_, worker)| worker.join())
             .collect::<Result<Vec<WorkerResult>, PoolError>>()
     }
 }
@@ -63,6 +376,7 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::thread;
 
     #[test]
     fn lifetime_test() {
@@ -78,8 +392,124 @@ mod tests {
             panic!("This should panic!");
         });
 
+        let stats = pool.stats();
         let result = pool.join().unwrap();
 
-        assert_eq!(result[0], WorkerResult::Panic);
+        assert_eq!(result[0], WorkerResult { id: 0, panics: 1 });
+        assert_eq!(stats.panics(), 1);
+        assert_eq!(stats.queued(), 0);
+    }
+
+    /// Work that waits for `gate` to be unlocked, if any
+    fn gated(gate: Option<Arc<Mutex<()>>>) -> impl FnOnce(&()) + Send + UnwindSafe {
+        move |_: &()| {
+            if let Some(gate) = gate {
+                let _open = gate.lock();
+            }
+        }
+    }
+
+    #[test]
+    fn saturation_test() {
+        let pool = ThreadPool::bounded(1, 1, ());
+        let gate = Arc::new(Mutex::new(()));
+        let closed = gate.lock().unwrap();
+
+        // Keeps the only worker busy
+        assert!(pool.try_do_work(gated(Some(gate.clone()))).is_ok());
+        while pool.stats().queued() > 0 {
+            thread::yield_now();
+        }
+
+        assert!(pool.try_do_work(gated(None)).is_ok());
+        assert!(pool.try_do_work(gated(None)).is_err());
+        assert_eq!(pool.stats().queued(), 1);
+
+        drop(closed);
+        pool.join().unwrap();
+    }
+
+    #[test]
+    fn elastic_test() {
+        let pool = ThreadPool::elastic(1, 3, Duration::from_millis(50), 10, ());
+        let stats = pool.stats();
+        assert_eq!(stats.workers(), 1);
+
+        let gate = Arc::new(Mutex::new(()));
+        let closed = gate.lock().unwrap();
+        for busy in 1..=3 {
+            pool.do_work(gated(Some(gate.clone())));
+            while stats.busy() < busy {
+                thread::yield_now();
+            }
+        }
+        assert_eq!(stats.workers(), 3);
+        assert_eq!(stats.utilization(), 1.0);
+
+        // The pool is at its maximum, this one has to wait
+        pool.do_work(gated(None));
+        assert_eq!(stats.workers(), 3);
+
+        drop(closed);
+        while stats.workers() > 1 {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(stats.busy(), 0);
+        assert_eq!(stats.queued(), 0);
+
+        assert_eq!(pool.join().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn stealing_test() {
+        let done = Arc::new(AtomicUsize::new(0));
+        let pool = ThreadPoolBuilder::new(4)
+            .max_queued(16)
+            .work_stealing()
+            .build(done.clone());
+
+        for _ in 0..1000 {
+            pool.do_work(|done: &Arc<AtomicUsize>| {
+                done.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+
+        let stats = pool.stats();
+        assert_eq!(pool.join().unwrap().len(), 4);
+        assert_eq!(done.load(Ordering::SeqCst), 1000);
+        assert_eq!(stats.queued(), 0);
+    }
+
+    #[test]
+    fn stealing_saturation_test() {
+        let pool = ThreadPoolBuilder::new(1)
+            .max_queued(4)
+            .work_stealing()
+            .build(());
+        let stats = pool.stats();
+        let first = Arc::new(Mutex::new(()));
+        let first_closed = first.lock().unwrap();
+        let rest = Arc::new(Mutex::new(()));
+        let rest_closed = rest.lock().unwrap();
+
+        assert!(pool.try_do_work(gated(Some(first.clone()))).is_ok());
+        while stats.busy() < 1 {
+            thread::yield_now();
+        }
+        for _ in 0..4 {
+            assert!(pool.try_do_work(gated(Some(rest.clone()))).is_ok());
+        }
+
+        // The only worker takes the next work and the three after it in a batch
+        drop(first_closed);
+        while stats.queued() > 3 {
+            thread::yield_now();
+        }
+        assert!(pool.try_do_work(gated(None)).is_ok());
+        assert!(pool.try_do_work(gated(None)).is_err());
+        assert_eq!(stats.queued(), 4);
+
+        drop(rest_closed);
+        pool.join().unwrap();
     }
 }
@@ -0,0 +1,280 @@
+use super::worker::WorkerMessage;
+use super::{PoolStats, Task, ThreadPool, Work};
+use crossbeam::channel;
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::panic::{RefUnwindSafe, UnwindSafe};
+use std::sync::atomic::{self, AtomicBool};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Cancels a scheduled task
+#[derive(Debug, Clone)]
+pub struct ScheduleHandle {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl ScheduleHandle {
+    /// Stop the task from running again, a run that already started is not interrupted
+    pub fn cancel(&self) {
+        self.cancelled.store(true, atomic::Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(atomic::Ordering::SeqCst)
+    }
+}
+
+/// What a scheduled task sends to the workers when it is due
+enum Job<T> {
+    Once(T),
+    /// The work of each run, `None` to skip a run
+    Every(Duration, Box<dyn Fn() -> Option<T> + Send>),
+}
+
+struct Entry<T> {
+    due: Instant,
+    /// Orders entries that are due at the same time by when they were scheduled
+    seq: usize,
+    job: Job<T>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl<T> PartialEq for Entry<T> {
+    fn eq(&self, other: &Self) -> bool {
+        (self.due, self.seq) == (other.due, other.seq)
+    }
+}
+
+impl<T> Eq for Entry<T> {}
+
+impl<T> PartialOrd for Entry<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Entry<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.due, self.seq).cmp(&(other.due, other.seq))
+    }
+}
+
+/// Thread that sends scheduled work to the workers of a pool once it is due
+/// The thread also stops if the timer is dropped without being stopped
+pub(crate) struct Timer<T> {
+    entries: channel::Sender<Entry<T>>,
+    next_seq: usize,
+    thread: thread::JoinHandle<()>,
+}
+
+impl<T: Send + 'static> Timer<T> {
+    pub fn start(
+        name: Option<String>,
+        workers: channel::Sender<WorkerMessage<T>>,
+        stats: Arc<PoolStats>,
+    ) -> Self {
+        let (entries, receiver) = channel::unbounded();
+
+        let mut builder = thread::Builder::new();
+        if let Some(name) = name {
+            builder = builder.name(name);
+        }
+        let thread = builder
+            .spawn(move || run(&receiver, &workers, &stats))
+            .expect("failed to spawn the timer thread");
+
+        Self {
+            entries,
+            next_seq: 0,
+            thread,
+        }
+    }
+
+    fn schedule(&mut self, due: Instant, job: Job<T>) -> ScheduleHandle {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        self.entries.send(Entry {
+            due,
+            seq: self.next_seq,
+            job,
+            cancelled: cancelled.clone(),
+        });
+        self.next_seq += 1;
+
+        ScheduleHandle { cancelled }
+    }
+
+    /// Drop every scheduled task and wait for the thread to be done
+    pub fn stop(self) {
+        drop(self.entries);
+        let _ = self.thread.join();
+    }
+}
+
+/// Wait for entries to be due and send their work, until the timer is stopped
+fn run<T>(
+    receiver: &channel::Receiver<Entry<T>>,
+    workers: &channel::Sender<WorkerMessage<T>>,
+    stats: &PoolStats,
+) {
+    let mut entries = BinaryHeap::new();
+
+    loop {
+        let now = Instant::now();
+        while entries
+            .peek()
+            .is_some_and(|next: &Reverse<Entry<T>>| next.0.due <= now)
+        {
+            let Reverse(mut entry) = entries.pop().unwrap();
+            if entry.cancelled.load(atomic::Ordering::SeqCst) {
+                continue;
+            }
+
+            match entry.job {
+                Job::Once(work) => send(workers, stats, work),
+                Job::Every(interval, ref make_work) => {
+                    if let Some(work) = make_work() {
+                        send(workers, stats, work);
+                    }
+                    // Runs that were missed are skipped rather than caught up
+                    entry.due = (entry.due + interval).max(now);
+                    entries.push(Reverse(entry));
+                }
+            }
+        }
+
+        let received = match entries.peek() {
+            Some(Reverse(next)) => {
+                let timeout = channel::after(next.due - now);
+                channel::Select::new()
+                    .recv(receiver, Some)
+                    .recv(&timeout, |_| None)
+                    .wait()
+            }
+            None => Some(receiver.recv()),
+        };
+
+        match received {
+            Some(Some(entry)) => entries.push(Reverse(entry)),
+            // Stopped, the remaining entries are dropped
+            Some(None) => return,
+            None => {}
+        }
+    }
+}
+
+fn send<T>(workers: &channel::Sender<WorkerMessage<T>>, stats: &PoolStats, work: T) {
+    stats.queued.fetch_add(1, atomic::Ordering::SeqCst);
+    workers.send(WorkerMessage::Work(work));
+}
+
+impl<S, T> ThreadPool<S, T>
+where
+    S: Clone + RefUnwindSafe + UnwindSafe + Send + Sync + 'static,
+    T: Work<S> + From<Task<S>>,
+{
+    /// Run `task` on a worker once `delay` has passed
+    pub fn schedule_after(
+        &self,
+        delay: Duration,
+        task: impl FnOnce(&S) + Send + UnwindSafe + 'static,
+    ) -> ScheduleHandle {
+        self.timer(|timer| {
+            timer.schedule(Instant::now() + delay, Job::Once(T::from(Task::new(task))))
+        })
+    }
+
+    /// Run `task` on a worker every `interval`, starting one interval from now
+    /// A run is skipped if the previous one is still going
+    pub fn schedule_every(
+        &self,
+        interval: Duration,
+        task: impl Fn(&S) + Send + Sync + RefUnwindSafe + 'static,
+    ) -> ScheduleHandle {
+        let task = Arc::new(task);
+        let running = Arc::new(AtomicBool::new(false));
+
+        let make_work = move || {
+            if running.swap(true, atomic::Ordering::SeqCst) {
+                return None;
+            }
+
+            let task = task.clone();
+            let running = Running(running.clone());
+            Some(T::from(Task::new(move |state: &S| {
+                let _running = running;
+                task(state);
+            })))
+        };
+
+        self.timer(|timer| {
+            timer.schedule(
+                Instant::now() + interval,
+                Job::Every(interval, Box::new(make_work)),
+            )
+        })
+    }
+}
+
+/// Marks a periodic task as done running once dropped, even if it panicked
+struct Running(Arc<AtomicBool>);
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.0.store(false, atomic::Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    #[test]
+    fn schedule_test() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let pool: ThreadPool<_, Task<_>> = ThreadPool::new(2, runs.clone());
+        let count = |runs: &Arc<AtomicUsize>| {
+            runs.fetch_add(1, atomic::Ordering::SeqCst);
+        };
+
+        let every = pool.schedule_every(Duration::from_millis(10), count);
+        let cancelled = pool.schedule_after(Duration::from_millis(10), |runs| {
+            runs.fetch_add(1000, atomic::Ordering::SeqCst);
+        });
+        cancelled.cancel();
+
+        let (sender, receiver) = channel::bounded(1);
+        pool.schedule_after(Duration::from_millis(50), move |_| sender.send(()));
+        receiver.recv().unwrap();
+        every.cancel();
+
+        let ran = runs.load(atomic::Ordering::SeqCst);
+        assert!(ran >= 2 && ran < 1000, "ran {} times", ran);
+        thread::sleep(Duration::from_millis(30));
+        assert!(runs.load(atomic::Ordering::SeqCst) <= ran + 1);
+
+        // Never runs, and doesn't hold up the pool
+        pool.schedule_after(Duration::from_secs(3600), count);
+        let started = Instant::now();
+        pool.join().unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn cancel_scheduled_test() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let pool: ThreadPool<_, Task<_>> = ThreadPool::new(1, runs.clone());
+
+        pool.schedule_every(Duration::from_millis(10), |runs: &Arc<AtomicUsize>| {
+            runs.fetch_add(1, atomic::Ordering::SeqCst);
+        });
+        // Without joining the pool, as when its workers are left to finish on their own
+        pool.cancel_scheduled();
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(runs.load(atomic::Ordering::SeqCst), 0);
+
+        pool.join().unwrap();
+    }
+}
@@ -0,0 +1,145 @@
+use super::worker::WorkerMessage;
+use super::PoolStats;
+use crossbeam::channel;
+use crossbeam::deque;
+use std::sync::{Arc, RwLock};
+
+/// Most messages a worker takes from the injector at once
+const BATCH: usize = 8;
+
+/// Queues of the workers of a work-stealing pool, by worker id
+pub(crate) type Stealers<T> = RwLock<Vec<(usize, deque::Stealer<WorkerMessage<T>>)>>;
+
+/// Queue of a worker in a work-stealing pool
+///
+/// Work is sent to the pool's channel, the injector. A worker that takes work from it while
+/// every other worker is busy takes a batch, so that workers go through the shared channel less
+/// often when the pool is saturated. Workers that run out of work steal from the others' queues
+/// before waiting on the injector.
+/// A worker that goes idle just as another takes a batch waits on the injector, the batch is then
+/// left to the worker that took it, which is why batches are kept small.
+pub(crate) struct LocalQueue<T> {
+    id: usize,
+    deque: deque::Worker<WorkerMessage<T>>,
+    stealers: Arc<Stealers<T>>,
+}
+
+impl<T> LocalQueue<T> {
+    /// Create the queue of worker `id` and let the other workers steal from it
+    pub fn register(id: usize, stealers: Arc<Stealers<T>>) -> Self {
+        let (deque, stealer) = deque::fifo();
+        stealers.write().unwrap().push((id, stealer));
+
+        Self {
+            id,
+            deque,
+            stealers,
+        }
+    }
+
+    /// Next message for the worker without waiting, from its own queue, the injector, or the
+    /// queue of another worker
+    pub fn next(
+        &self,
+        injector: &channel::Receiver<WorkerMessage<T>>,
+        stats: &PoolStats,
+    ) -> Option<WorkerMessage<T>> {
+        if let Some(message) = self.deque.pop() {
+            return Some(message);
+        }
+
+        if let Some(message) = injector.try_recv() {
+            // This worker is not counted as busy yet
+            if stats.busy() + 1 >= stats.workers() {
+                self.take_batch(injector);
+            }
+            return Some(message);
+        }
+
+        self.steal()
+    }
+
+    /// Move up to a batch of messages from the injector to this worker's queue
+    /// A `Resign` ends the batch, so that the worker only resigns once its queue is empty
+    fn take_batch(&self, injector: &channel::Receiver<WorkerMessage<T>>) {
+        for _ in 1..BATCH {
+            match injector.try_recv() {
+                Some(message @ WorkerMessage::Work(_)) => self.deque.push(message),
+                Some(resign) => {
+                    self.deque.push(resign);
+                    return;
+                }
+                None => return,
+            }
+        }
+    }
+
+    /// Take a message from another worker's queue, starting with the worker after this one
+    fn steal(&self) -> Option<WorkerMessage<T>> {
+        let stealers = self.stealers.read().unwrap();
+        let start = stealers
+            .iter()
+            .position(|(id, _)| *id == self.id)
+            .map_or(0, |index| index + 1);
+
+        stealers
+            .iter()
+            .cycle()
+            .skip(start)
+            .take(stealers.len())
+            .filter(|(id, _)| *id != self.id)
+            .filter_map(|(_, stealer)| stealer.steal())
+            .next()
+    }
+}
+
+impl<T> Drop for LocalQueue<T> {
+    fn drop(&mut self) {
+        let mut stealers = self.stealers.write().unwrap();
+        stealers.retain(|(id, _)| *id != self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn batch_and_steal() {
+        let stealers = Arc::new(Stealers::default());
+        let first = LocalQueue::register(0, stealers.clone());
+        let second = LocalQueue::register(1, stealers.clone());
+
+        // Every worker is busy but the one taking work
+        let stats = PoolStats::default();
+        stats.workers.store(2, Ordering::SeqCst);
+        stats.busy.store(1, Ordering::SeqCst);
+
+        let (sender, injector) = channel::unbounded();
+        for work in 0..3 {
+            sender.send(WorkerMessage::Work(work));
+        }
+        sender.send(WorkerMessage::Resign);
+
+        let work = |message| match message {
+            Some(WorkerMessage::Work(work)) => Some(work),
+            _ => None,
+        };
+        assert_eq!(work(first.next(&injector, &stats)), Some(0));
+        // Work 1, 2 and the resign were taken in a batch, the second worker steals from it
+        assert_eq!(work(second.next(&injector, &stats)), Some(1));
+        assert_eq!(work(first.next(&injector, &stats)), Some(2));
+        assert!(match first.next(&injector, &stats) {
+            Some(WorkerMessage::Resign) => true,
+            _ => false,
+        });
+        assert!(second.next(&injector, &stats).is_none());
+
+        sender.send(WorkerMessage::Work(3));
+        assert_eq!(work(second.next(&injector, &stats)), Some(3));
+
+        drop(first);
+        assert_eq!(stealers.read().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,184 @@
+use super::{ThreadPool, Work};
+use crossbeam::channel;
+use std::any::Any;
+use std::panic::{self, RefUnwindSafe, UnwindSafe};
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+/// Work of any type, so that one pool can run all kinds of work
+/// Pools of other work can take tasks too, if their work can be made from a task
+pub struct Task<S>(Box<dyn RunOnce<S> + Send + UnwindSafe>);
+
+/// `FnOnce` that can be called from a box
+trait RunOnce<S> {
+    fn run_once(self: Box<Self>, state: &S);
+}
+
+impl<S, F> RunOnce<S> for F
+where
+    F: FnOnce(&S),
+{
+    fn run_once(self: Box<Self>, state: &S) {
+        (*self)(state)
+    }
+}
+
+impl<S> Task<S> {
+    pub fn new(work: impl FnOnce(&S) + Send + UnwindSafe + 'static) -> Self {
+        Task(Box::new(work))
+    }
+}
+
+impl<S: 'static> Work<S> for Task<S> {
+    fn run(self, state: &S) {
+        self.0.run_once(state)
+    }
+}
+
+/// Submitted work that panicked
+#[derive(Debug, Fail)]
+#[fail(display = "Task panicked: {}", message)]
+pub struct Panicked {
+    pub message: String,
+}
+
+/// The message a panic was started with
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Box<Any>".to_string()
+    }
+}
+
+/// Waits for the result of submitted work
+pub struct TaskHandle<R> {
+    receiver: channel::Receiver<Result<R, Panicked>>,
+}
+
+impl<R> TaskHandle<R> {
+    /// Wait until the work is done
+    pub fn wait(self) -> Result<R, Panicked> {
+        self.receiver.recv().unwrap_or_else(dropped)
+    }
+
+    /// Wait until the work is done, for at most `timeout`
+    /// Returns `None` if the work is not done yet, the handle can then be waited on again
+    pub fn wait_timeout(&self, timeout: Duration) -> Option<Result<R, Panicked>> {
+        let timeout = channel::after(timeout);
+        channel::Select::new()
+            .recv(&self.receiver, |result| {
+                Some(result.unwrap_or_else(dropped))
+            })
+            .recv(&timeout, |_| None)
+            .wait()
+    }
+}
+
+/// No result is coming, the work was dropped without running or its result was already taken
+fn dropped<R>() -> Result<R, Panicked> {
+    Err(Panicked {
+        message: "the task was dropped before it ran".to_string(),
+    })
+}
+
+impl<S, T> ThreadPool<S, T>
+where
+    S: Clone + RefUnwindSafe + UnwindSafe + Send + Sync + 'static,
+    T: Work<S> + From<Task<S>>,
+{
+    /// Send work to a worker thread and get a handle to wait for its result
+    /// A panic of the work is caught and returned by the handle, the worker keeps running
+    pub fn submit<F, R>(&self, work: F) -> TaskHandle<R>
+    where
+        F: FnOnce(&S) -> R + Send + UnwindSafe + 'static,
+        R: Send + 'static,
+    {
+        let (sender, receiver) = channel::bounded(1);
+        let stats = self.stats();
+
+        self.do_work(T::from(Task::new(move |state: &S| {
+            let result = panic::catch_unwind(move || work(state)).map_err(|payload| {
+                stats.panics.fetch_add(1, Ordering::SeqCst);
+                Panicked {
+                    message: panic_message(&*payload),
+                }
+            });
+            sender.send(result);
+        })));
+
+        TaskHandle { receiver }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn submit_test() {
+        let pool: ThreadPool<i32, Task<i32>> = ThreadPool::new(2, 40);
+
+        let sum = pool.submit(|base: &i32| (1..=10).sum::<i32>() + base);
+        let panicked = pool.submit(|_: &i32| -> i32 { panic!("no result") });
+        assert_eq!(sum.wait().unwrap(), 95);
+        assert_eq!(panicked.wait().unwrap_err().message, "no result");
+
+        let gate = Arc::new(Mutex::new(()));
+        let closed = gate.lock().unwrap();
+        let gated = {
+            let gate = gate.clone();
+            pool.submit(move |_: &i32| {
+                let _open = gate.lock();
+                "done"
+            })
+        };
+        assert!(gated.wait_timeout(Duration::from_millis(20)).is_none());
+        drop(closed);
+        assert_eq!(
+            gated.wait_timeout(Duration::from_secs(5)).unwrap().unwrap(),
+            "done"
+        );
+
+        let stats = pool.stats();
+        let results = pool.join().unwrap();
+        assert_eq!(stats.panics(), 1);
+        // Panics of submitted work are caught before they reach the worker
+        assert!(results.iter().all(|result| result.panics == 0));
+    }
+
+    /// Work of a pool that also runs tasks
+    enum Job {
+        Add(i32),
+        Task(Task<Arc<Mutex<i32>>>),
+    }
+
+    impl From<Task<Arc<Mutex<i32>>>> for Job {
+        fn from(task: Task<Arc<Mutex<i32>>>) -> Self {
+            Job::Task(task)
+        }
+    }
+
+    impl Work<Arc<Mutex<i32>>> for Job {
+        fn run(self, total: &Arc<Mutex<i32>>) {
+            match self {
+                Job::Add(value) => *total.lock().unwrap() += value,
+                Job::Task(task) => task.run(total),
+            }
+        }
+    }
+
+    #[test]
+    fn submit_to_other_work() {
+        let pool = ThreadPool::new(1, Arc::new(Mutex::new(0)));
+
+        pool.do_work(Job::Add(2));
+        let total = pool.submit(|total: &Arc<Mutex<i32>>| *total.lock().unwrap());
+        assert_eq!(total.wait().unwrap(), 2);
+
+        pool.join().unwrap();
+    }
+}
@@ -1,9 +1,14 @@
-use super::PoolError;
+use super::affinity;
+use super::stealing::{LocalQueue, Stealers};
+use super::{PoolError, PoolStats, Work};
 use core::marker::PhantomData;
 use crossbeam::channel;
 use std::panic;
-use std::panic::{RefUnwindSafe, UnwindSafe};
+use std::panic::{AssertUnwindSafe, RefUnwindSafe};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
 use std::thread;
+use std::time::Duration;
 
 /// Message sent to worker
 pub enum WorkerMessage<T> {
@@ -11,16 +16,41 @@ pub enum WorkerMessage<T> {
     Resign,
 }
 
-#[derive(Debug, PartialOrd, PartialEq)]
-pub enum WorkerResult {
-    Panic,
-    Ok,
+/// What a worker did, once joined
+#[derive(Debug, PartialEq)]
+pub struct WorkerResult {
+    pub id: usize,
+    /// Work that panicked on this worker
+    pub panics: usize,
+}
+
+/// How a worker of an elastic pool retires
+#[derive(Clone)]
+pub struct Retire {
+    /// Workers only retire while the pool has more than this many
+    pub min: usize,
+    pub idle_timeout: Duration,
+    /// Where the worker sends its id once it retired
+    pub retired: channel::Sender<usize>,
+}
+
+/// How worker threads are set up
+#[derive(Clone, Default)]
+pub struct ThreadOptions {
+    /// Threads are named "<prefix>-<worker id>"
+    pub name_prefix: Option<String>,
+    /// Stack size of the threads in bytes, the default of `std::thread` if not set
+    pub stack_size: Option<usize>,
+    /// CPUs the workers are pinned to in turn, workers are not pinned if empty
+    pub cpus: Vec<usize>,
+    /// Called with the worker id on the worker thread, before the worker takes any work
+    pub init: Option<Arc<dyn Fn(usize) + Send + Sync>>,
 }
 
 /// Worker manages a thread that does work
 pub struct Worker<S, T>
 where
-    T: FnOnce(&S) + Send + 'static + UnwindSafe,
+    T: Work<S>,
     S: Send + Sync + RefUnwindSafe + 'static,
 {
     join_handle: thread::JoinHandle<WorkerResult>,
@@ -28,20 +58,68 @@ where
     _s: PhantomData<S>,
 }
 
+/// Counts a worker as busy until dropped, even if its work panics
+struct Busy<'a>(&'a PoolStats);
+
+impl<'a> Drop for Busy<'a> {
+    fn drop(&mut self) {
+        self.0.busy.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
 impl<S, T> Worker<S, T>
 where
     S: Send + Sync + RefUnwindSafe + 'static,
-    T: FnOnce(&S) + Send + 'static + UnwindSafe,
+    T: Work<S>,
 {
-    pub fn spawn(receiver: channel::Receiver<WorkerMessage<T>>, state: S) -> Self {
-        let join_handle = thread::spawn(move || {
-            let mut panic_occurred = false;
+    /// Spawn a worker, which retires as described by `retire` if given
+    /// `id` identifies the worker in the pool's stats, and is what it sends once retired
+    /// Given `stealers`, the worker gets its own queue, see `LocalQueue`
+    pub fn spawn(
+        id: usize,
+        receiver: channel::Receiver<WorkerMessage<T>>,
+        state: S,
+        stats: Arc<PoolStats>,
+        retire: Option<Retire>,
+        threads: &ThreadOptions,
+        stealers: Option<Arc<Stealers<T>>>,
+    ) -> Self {
+        let mut builder = thread::Builder::new();
+        if let Some(prefix) = &threads.name_prefix {
+            builder = builder.name(format!("{}-{}", prefix, id));
+        }
+        if let Some(stack_size) = threads.stack_size {
+            builder = builder.stack_size(stack_size);
+        }
+        let cpu = match threads.cpus.len() {
+            0 => None,
+            cpus => Some(threads.cpus[id % cpus]),
+        };
+        let init = threads.init.clone();
+
+        let join_handle = builder.spawn(move || {
+            if let Some(cpu) = cpu {
+                if let Err(err) = affinity::pin_current_thread(cpu) {
+                    warn!(worker = id, cpu, error:% = err; "Could not pin the worker to its CPU");
+                }
+            }
+            if let Some(init) = init {
+                init(id);
+            }
+            // Work that panics does so while the queue is left as is
+            let local =
+                AssertUnwindSafe(stealers.map(|stealers| LocalQueue::register(id, stealers)));
+
+            let mut panics = 0;
             loop {
                 let result = panic::catch_unwind(|| {
-                    'msg_loop: while let Some(message) = receiver.recv() {
+                    while let Some(message) = receive(id, &receiver, &stats, &retire, &local) {
                         match message {
                             WorkerMessage::Work(work) => {
-                                work(&state);
+                                stats.queued.fetch_sub(1, Ordering::SeqCst);
+                                stats.busy.fetch_add(1, Ordering::SeqCst);
+                                let _busy = Busy(&stats);
+                                work.run(&state);
                             }
                             WorkerMessage::Resign => {
                                 return;
@@ -53,16 +131,16 @@ where
                 if let Ok(_) = result {
                     break;
                 } else {
-                    panic_occurred = true;
+                    panics += 1;
+                    stats.worker_panicked(id);
+                    error!(worker = id, panics; "Work panicked, the worker keeps running");
                 }
             }
 
-            if panic_occurred {
-                WorkerResult::Panic
-            } else {
-                WorkerResult::Ok
-            }
+            WorkerResult { id, panics }
         });
+        // Like `thread::spawn`, which panics if the OS can't create a thread
+        let join_handle = join_handle.expect("failed to spawn a worker thread");
 
         Self {
             join_handle,
@@ -78,6 +156,42 @@ where
     }
 }
 
+/// Wait for the next message
+/// A worker that can retire gets a `Resign` once it has been idle for long enough
+fn receive<T>(
+    id: usize,
+    receiver: &channel::Receiver<WorkerMessage<T>>,
+    stats: &PoolStats,
+    retire: &Option<Retire>,
+    local: &Option<LocalQueue<T>>,
+) -> Option<WorkerMessage<T>> {
+    if let Some(message) = local.as_ref().and_then(|local| local.next(receiver, stats)) {
+        return Some(message);
+    }
+
+    let retire = match retire {
+        Some(retire) => retire,
+        None => return receiver.recv(),
+    };
+
+    loop {
+        let timeout = channel::after(retire.idle_timeout);
+        let message = channel::Select::new()
+            .recv(receiver, Some)
+            .recv(&timeout, |_| None)
+            .wait();
+
+        match message {
+            Some(message) => return message,
+            None if stats.try_shrink(retire.min) => {
+                retire.retired.send(id);
+                return Some(WorkerMessage::Resign);
+            }
+            None => {}
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -85,7 +199,15 @@ mod tests {
     #[test]
     fn worker_lifetime() {
         let (s, r) = channel::unbounded();
-        let worker = Worker::<(), fn(&())>::spawn(r, ());
+        let worker = Worker::<(), fn(&())>::spawn(
+            0,
+            r,
+            (),
+            Arc::default(),
+            None,
+            &ThreadOptions::default(),
+            None,
+        );
 
         s.send(WorkerMessage::Resign);
         worker.join().unwrap();
@@ -94,11 +216,22 @@ mod tests {
     #[test]
     fn worker_work() {
         let (s, r) = channel::unbounded();
-        let worker = Worker::<(), fn(&())>::spawn(r, ());
+        let stats = Arc::new(PoolStats::default());
+        let worker = Worker::<(), fn(&())>::spawn(
+            3,
+            r,
+            (),
+            stats.clone(),
+            None,
+            &ThreadOptions::default(),
+            None,
+        );
 
         s.send(WorkerMessage::Work(|_| panic!("This should panic!")));
+        s.send(WorkerMessage::Work(|_| panic!("This should panic too!")));
 
         s.send(WorkerMessage::Resign);
-        assert_eq!(worker.join().unwrap(), WorkerResult::Panic);
+        assert_eq!(worker.join().unwrap(), WorkerResult { id: 3, panics: 2 });
+        assert_eq!(stats.worker_panics().get(&3), Some(&2));
     }
 }
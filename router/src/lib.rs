@@ -183,10 +183,47 @@ impl<T: Debug, R> Router<T, R> {
     /// Returns `None` if no route could be found
     pub fn route(&self, path: impl Into<RouterPath>, data: T) -> Option<R> {
         let path = path.into();
+        let resolved = self.resolve(&path)?;
 
+        // This is needed to give the path overload to the endpoint if needed
+        let path_overload = match resolved.overload_from {
+            None => Vec::new(),
+            Some(overload_from) => path.parts[overload_from..]
+                .into_iter()
+                .map(|part| String::from_utf8(part.to_vec()).unwrap())
+                .collect(),
+        };
+
+        Some(resolved.endpoint.process(RoutedInfo {
+            data,
+            path_overload,
+        }))
+    }
+
+    /// Name of the route a path would be routed to, e.g. "/favicon.ico", or "/blog/*" for an
+    /// endpoint that is not strict
+    /// Returns `None` if the path goes to the 404 endpoint or to no endpoint at all
+    pub fn route_name(&self, path: impl Into<RouterPath>) -> Option<String> {
+        let path = path.into();
+
+        let matched = self.resolve(&path)?.matched?;
+        let name: Vec<_> = path.parts[..matched.parts]
+            .iter()
+            .map(|part| String::from_utf8_lossy(part))
+            .collect();
+        let name = name.join("/");
+
+        if matched.strict {
+            Some(name)
+        } else {
+            Some(format!("{}/*", name))
+        }
+    }
+
+    /// Find the endpoint a path goes to
+    fn resolve(&self, path: &RouterPath) -> Option<Resolved<'_, T, R>> {
         let mut current_router = self;
 
-        // This is needed to give the path overload to the endpoint if needed
         let mut failed_to_match = false;
         let mut last_path_index = None;
 
@@ -200,47 +237,100 @@ impl<T: Debug, R> Router<T, R> {
             }
         }
 
+        let matched_parts = last_path_index.map_or(0, |index| index + 1);
+
         if let Some(endpoint) = &current_router.endpoint {
             if endpoint.use_strict_path_matching() {
                 if !failed_to_match {
-                    return Some(endpoint.process(RoutedInfo {
-                        data,
-                        path_overload: Vec::new(),
-                    }));
+                    return Some(Resolved {
+                        endpoint: endpoint.as_ref(),
+                        overload_from: None,
+                        matched: Some(Matched {
+                            parts: matched_parts,
+                            strict: true,
+                        }),
+                    });
                 }
-            } else if let Some(last_path_index) = last_path_index {
-                return Some(
-                    endpoint.process(RoutedInfo {
-                        data,
-                        path_overload: path.parts[last_path_index..]
-                            .into_iter()
-                            .map(|part| String::from_utf8(part.to_vec()).unwrap())
-                            .collect(),
-                    }),
-                );
             } else {
-                return Some(endpoint.process(RoutedInfo {
-                    data,
-                    path_overload: Vec::new(),
-                }));
+                return Some(Resolved {
+                    endpoint: endpoint.as_ref(),
+                    overload_from: last_path_index,
+                    matched: Some(Matched {
+                        parts: matched_parts,
+                        strict: false,
+                    }),
+                });
             }
         }
 
         if let Some(endpoint_404) = &self.endpoint_404 {
-            let overload = match last_path_index {
-                None => Vec::new(),
-                Some(last_path_index) => path.parts[last_path_index..]
-                    .into_iter()
-                    .map(|part| String::from_utf8(part.to_vec()).unwrap())
-                    .collect(),
-            };
-
-            return Some(endpoint_404.process(RoutedInfo {
-                data,
-                path_overload: overload,
-            }));
+            return Some(Resolved {
+                endpoint: endpoint_404.as_ref(),
+                overload_from: last_path_index,
+                matched: None,
+            });
         }
 
         None
     }
 }
+
+/// Endpoint found for a path
+struct Resolved<'a, T: Debug, R> {
+    endpoint: &'a dyn Endpoint<T, R>,
+    /// Parts from this index on are given to the endpoint as path overload
+    overload_from: Option<usize>,
+    /// `None` when the path goes to the 404 endpoint
+    matched: Option<Matched>,
+}
+
+/// Route matched by a path
+struct Matched {
+    /// Number of path parts in the route
+    parts: usize,
+    strict: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Named(&'static str);
+
+    impl Endpoint<(), &'static str> for Named {
+        fn process(&self, _: RoutedInfo<()>) -> &'static str {
+            self.0
+        }
+    }
+
+    struct Prefix;
+
+    impl Endpoint<(), &'static str> for Prefix {
+        fn use_strict_path_matching(&self) -> bool {
+            false
+        }
+
+        fn process(&self, _: RoutedInfo<()>) -> &'static str {
+            "prefix"
+        }
+    }
+
+    #[test]
+    fn route_names() {
+        let mut router = Router::default();
+        router.add_path("/", Named("index"));
+        router.add_path("/favicon.ico", Named("icon"));
+        router.add_path("/blog", Prefix);
+
+        assert_eq!(router.route("/favicon.ico", ()), Some("icon"));
+        assert_eq!(router.route_name("/favicon.ico").unwrap(), "/favicon.ico");
+        assert_eq!(router.route("/blog/post", ()), Some("prefix"));
+        assert_eq!(router.route_name("/blog/post").unwrap(), "/blog/*");
+        assert_eq!(router.route_name("/").unwrap(), "/");
+        assert_eq!(router.route_name("/missing"), None);
+
+        router.set_endpoint_404(Named("404"));
+        assert_eq!(router.route("/missing", ()), Some("404"));
+        assert_eq!(router.route_name("/missing"), None);
+    }
+}
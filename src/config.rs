@@ -0,0 +1,969 @@
+use getopts::Options;
+use http_server::{
+    AccessLog, AccessLogFormat, ClientLimits, ConnectionSettings, HealthChecks, Network, Proxy,
+    Rotation,
+};
+use log::LevelFilter;
+use serde_derive::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[derive(Debug, Fail)]
+pub enum ConfigError {
+    #[fail(display = "Could not read config file {:?}: {}", 0, 1)]
+    Read(PathBuf, std::io::Error),
+    #[fail(display = "Could not parse config file {:?}: {}", 0, 1)]
+    Parse(PathBuf, toml::de::Error),
+    #[fail(display = "{}", 0)]
+    Arguments(String),
+    #[fail(display = "Invalid configuration: {}", 0)]
+    Invalid(String),
+}
+
+/// Server configuration, read from a TOML file and overridden by command line flags
+///
+/// The static roots, cache policy, proxies and hosts are reloaded on SIGHUP, the other settings only apply
+/// when the server starts.
+///
+/// ```toml
+/// listen = ["0.0.0.0:80", "[::]:8080"]
+/// unix_sockets = ["/run/milton.sock"]
+/// workers = 40
+/// max_workers = 200
+/// worker_stack_size = 2097152
+/// worker_cpus = [0, 1, 2, 3]
+/// work_stealing = false
+/// static_root = "/srv/www"
+/// strict_hosts = false
+/// metrics_path = "/metrics"
+/// trusted_proxies = ["127.0.0.1"]
+///
+/// [timeouts]
+/// request_read = 5
+/// header_read = 10
+/// worker_idle = 60
+/// keep_alive = 5
+/// drain = 30
+///
+/// [limits]
+/// max_requests_per_connection = 100
+/// max_request_line = 8192
+/// max_header_size = 16384
+/// max_headers = 100
+/// max_queued = 1024
+///
+/// [cache]
+/// max_age = 1800
+///
+/// [clients]
+/// max_connections = 20
+/// requests_per_second = 50.0
+/// burst = 100
+/// ipv6_prefix = 64
+/// allowlist = ["127.0.0.1", "10.0.0.0/8"]
+///
+/// [health]
+/// liveness_path = "/healthz"
+/// readiness_path = "/readyz"
+/// max_queued = 1024
+///
+/// [logging]
+/// level = "info"
+/// sink = "file"
+/// path = "/var/log/milton/server.log"
+///
+/// [logging.modules]
+/// "http_server::event_loop" = "debug"
+///
+/// [access_log]
+/// path = "/var/log/milton/access.log"
+/// format = "combined"
+/// max_size = 104857600
+/// rotate_every = 86400
+///
+/// [[proxies]]
+/// prefix = "/app"
+/// upstream = "127.0.0.1:3000"
+/// connect_timeout = 5
+/// read_timeout = 30
+/// max_idle = 8
+///
+/// [[hosts]]
+/// names = ["blog.example.com", "*.blog.example.com"]
+/// static_root = "/srv/blog"
+///
+/// [[hosts.proxies]]
+/// prefix = "/comments"
+/// upstream = "127.0.0.1:4000"
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Addresses to accept connections on, e.g. "0.0.0.0:80" or "[::]:80"
+    pub listen: Vec<String>,
+    /// Unix domain sockets to accept connections on
+    pub unix_sockets: Vec<PathBuf>,
+    pub workers: usize,
+    /// Workers are added while connections wait for one, up to this many
+    pub max_workers: Option<usize>,
+    /// Stack size of the worker threads, in bytes
+    pub worker_stack_size: Option<usize>,
+    /// CPUs the workers are pinned to in turn
+    pub worker_cpus: Vec<usize>,
+    /// Workers take connections from their own queue and steal from each other's
+    pub work_stealing: bool,
+    /// Directory served by the default host
+    /// The pages built into the binary are served when this is not set
+    pub static_root: Option<PathBuf>,
+    /// Refuse requests for hosts that are not in `hosts`
+    pub strict_hosts: bool,
+    /// Path metrics are served at for every host, they are not served when this is not set
+    pub metrics_path: Option<String>,
+    /// Addresses of clients whose X-Request-ID header is trusted, e.g. a reverse proxy
+    pub trusted_proxies: Vec<String>,
+    pub timeouts: Timeouts,
+    pub limits: Limits,
+    pub cache: Cache,
+    pub clients: Clients,
+    /// Health checks are only served when this section is present
+    pub health: Option<Health>,
+    pub logging: Logging,
+    pub access_log: Option<AccessLogConfig>,
+    /// Paths of the default host forwarded to upstream servers
+    pub proxies: Vec<ProxyConfig>,
+    pub hosts: Vec<VirtualHost>,
+    pub tls: Option<Tls>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            listen: vec!["0.0.0.0:80".to_string()],
+            unix_sockets: Vec::new(),
+            workers: 40,
+            max_workers: None,
+            worker_stack_size: None,
+            worker_cpus: Vec::new(),
+            work_stealing: false,
+            static_root: None,
+            strict_hosts: false,
+            metrics_path: None,
+            trusted_proxies: Vec::new(),
+            timeouts: Timeouts::default(),
+            limits: Limits::default(),
+            cache: Cache::default(),
+            clients: Clients::default(),
+            health: None,
+            logging: Logging::default(),
+            access_log: None,
+            proxies: Vec::new(),
+            hosts: Vec::new(),
+            tls: None,
+        }
+    }
+}
+
+/// Timeouts, in seconds
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Timeouts {
+    pub request_read: u64,
+    /// Total time allowed to receive the request line and headers
+    pub header_read: u64,
+    pub keep_alive: u64,
+    pub drain: u64,
+    /// How long workers above `workers` stay idle before retiring
+    pub worker_idle: u64,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        let settings = ConnectionSettings::default();
+        Self {
+            request_read: settings.request_read_timeout.as_secs(),
+            header_read: settings.header_read_timeout.as_secs(),
+            keep_alive: settings.keep_alive_timeout.as_secs(),
+            drain: settings.drain_timeout.as_secs(),
+            worker_idle: settings.worker_idle_timeout.as_secs(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    pub max_requests_per_connection: usize,
+    /// In bytes
+    pub max_request_line: usize,
+    /// In bytes, for every header of a request together
+    pub max_header_size: usize,
+    pub max_headers: usize,
+    /// Connections waiting for a worker above which new requests are refused
+    pub max_queued: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        let settings = ConnectionSettings::default();
+        Self {
+            max_requests_per_connection: settings.max_requests,
+            max_request_line: settings.max_request_line,
+            max_header_size: settings.max_header_size,
+            max_headers: settings.max_headers,
+            max_queued: settings.max_queued,
+        }
+    }
+}
+
+/// Cache policy of static files
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Cache {
+    /// How long clients may cache files, in seconds
+    pub max_age: u64,
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Self { max_age: 1800 }
+    }
+}
+
+/// Limits of every client address, clients are not limited by default
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Clients {
+    pub max_connections: Option<usize>,
+    pub requests_per_second: Option<f64>,
+    pub burst: u32,
+    /// IPv6 clients are limited by network of this many bits
+    pub ipv6_prefix: u8,
+    /// Addresses or networks, e.g. "10.0.0.0/8", that are never limited
+    pub allowlist: Vec<String>,
+}
+
+impl Default for Clients {
+    fn default() -> Self {
+        let limits = ClientLimits::default();
+        Self {
+            max_connections: limits.max_connections,
+            requests_per_second: limits.requests_per_second,
+            burst: limits.burst,
+            ipv6_prefix: limits.ipv6_prefix,
+            allowlist: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Health {
+    pub liveness_path: String,
+    pub readiness_path: String,
+    /// Connections waiting for a worker from which the server reports not ready
+    /// `limits.max_queued` if not set
+    pub max_queued: Option<usize>,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        let checks = HealthChecks::default();
+        Self {
+            liveness_path: checks.liveness_path,
+            readiness_path: checks.readiness_path,
+            max_queued: checks.max_queued,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Logging {
+    /// One of "off", "error", "warn", "info", "debug" or "trace"
+    pub level: String,
+    /// Levels of specific modules, e.g. `"http_server::event_loop" = "debug"`
+    pub modules: BTreeMap<String, String>,
+    /// One of "stderr", "file", "syslog" or "journald"
+    pub sink: String,
+    /// File written to by the "file" sink
+    pub path: Option<PathBuf>,
+}
+
+impl Default for Logging {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            modules: BTreeMap::new(),
+            sink: "stderr".to_string(),
+            path: None,
+        }
+    }
+}
+
+impl Logging {
+    pub fn level(&self) -> Result<LevelFilter, ConfigError> {
+        parse_level(&self.level)
+    }
+
+    /// Levels by module
+    pub fn module_levels(&self) -> Result<Vec<(String, LevelFilter)>, ConfigError> {
+        self.modules
+            .iter()
+            .map(|(module, level)| Ok((module.clone(), parse_level(level)?)))
+            .collect()
+    }
+
+    pub fn sink(&self) -> Result<LogSink, ConfigError> {
+        match (self.sink.as_str(), &self.path) {
+            ("stderr", _) => Ok(LogSink::Stderr),
+            ("file", Some(path)) => Ok(LogSink::File(path.clone())),
+            ("file", None) => Err(ConfigError::Invalid(
+                "the file log sink needs a path".to_string(),
+            )),
+            ("syslog", _) => Ok(LogSink::Syslog),
+            ("journald", _) => Ok(LogSink::Journald),
+            (sink, _) => Err(ConfigError::Invalid(format!("invalid log sink: {}", sink))),
+        }
+    }
+}
+
+/// Where log lines are written
+#[derive(Debug)]
+pub enum LogSink {
+    Stderr,
+    File(PathBuf),
+    Syslog,
+    Journald,
+}
+
+fn parse_level(level: &str) -> Result<LevelFilter, ConfigError> {
+    level
+        .parse()
+        .map_err(|_| ConfigError::Invalid(format!("invalid log level: {}", level)))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AccessLogConfig {
+    pub path: PathBuf,
+    /// One of "common", "combined" or "json"
+    #[serde(default = "AccessLogConfig::default_format")]
+    pub format: String,
+    /// Rotate the file once it is bigger than this many bytes
+    pub max_size: Option<u64>,
+    /// Rotate the file after this many seconds
+    pub rotate_every: Option<u64>,
+}
+
+impl AccessLogConfig {
+    fn default_format() -> String {
+        "combined".to_string()
+    }
+
+    fn format(&self) -> Result<AccessLogFormat, ConfigError> {
+        match self.format.as_str() {
+            "common" => Ok(AccessLogFormat::Common),
+            "combined" => Ok(AccessLogFormat::Combined),
+            "json" => Ok(AccessLogFormat::Json),
+            format => Err(ConfigError::Invalid(format!(
+                "invalid access log format: {}",
+                format
+            ))),
+        }
+    }
+}
+
+/// Domains served from their own directory
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VirtualHost {
+    /// Domains such as "example.com" or "*.example.com"
+    pub names: Vec<String>,
+    pub static_root: PathBuf,
+    /// Paths of the host forwarded to upstream servers
+    #[serde(default)]
+    pub proxies: Vec<ProxyConfig>,
+}
+
+/// Path prefix forwarded to an upstream HTTP/1.1 server
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProxyConfig {
+    /// e.g. "/app", for which "/app/users" is forwarded as "/users"
+    pub prefix: String,
+    /// e.g. "127.0.0.1:3000"
+    pub upstream: String,
+    /// In seconds
+    pub connect_timeout: Option<u64>,
+    /// How long the upstream has to answer, in seconds
+    pub read_timeout: Option<u64>,
+    /// Most idle connections kept open to the upstream
+    pub max_idle: Option<usize>,
+}
+
+impl ProxyConfig {
+    /// Route the proxy is added at
+    pub fn route(&self) -> &str {
+        self.prefix.trim_end_matches('/')
+    }
+
+    pub fn proxy(&self) -> Result<Proxy, ConfigError> {
+        let invalid = |message: String| Err(ConfigError::Invalid(message));
+
+        if !self.prefix.starts_with('/') || self.route().is_empty() {
+            return invalid(format!(
+                "proxy prefix {} needs to start with / and not be / alone",
+                self.prefix
+            ));
+        }
+        if self.connect_timeout == Some(0) || self.read_timeout == Some(0) {
+            return invalid(format!(
+                "timeouts of the proxy for {} need to be at least 1",
+                self.prefix
+            ));
+        }
+
+        let upstream = self.upstream.parse().map_err(|_| {
+            ConfigError::Invalid(format!("invalid upstream address: {}", self.upstream))
+        })?;
+
+        let mut proxy = Proxy::new(upstream);
+        if let Some(timeout) = self.connect_timeout {
+            proxy.connect_timeout(Duration::from_secs(timeout));
+        }
+        if let Some(timeout) = self.read_timeout {
+            proxy.read_timeout(Duration::from_secs(timeout));
+        }
+        if let Some(max_idle) = self.max_idle {
+            proxy.max_idle(max_idle);
+        }
+
+        Ok(proxy)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Tls {
+    pub certificate: PathBuf,
+    pub key: PathBuf,
+}
+
+const USAGE: &str = "Usage: milton [options]";
+
+impl Config {
+    /// Load the configuration from command line arguments (without the program name)
+    /// Returns `Ok(None)` when only the help was asked for
+    pub fn from_args(args: &[String]) -> Result<Option<Self>, ConfigError> {
+        let mut options = Options::new();
+        options
+            .optopt("c", "config", "read the configuration from FILE", "FILE")
+            .optmulti("l", "listen", "accept connections on ADDR", "ADDR")
+            .optmulti(
+                "",
+                "unix",
+                "accept connections on the unix socket PATH",
+                "PATH",
+            ).optopt("w", "workers", "number of worker threads", "COUNT")
+            .optopt("", "static-root", "serve the default host from DIR", "DIR")
+            .optflag("", "strict-hosts", "refuse requests for unknown hosts")
+            .optopt(
+                "",
+                "log-level",
+                "off, error, warn, info, debug or trace",
+                "LEVEL",
+            ).optopt("", "log-sink", "stderr, file, syslog or journald", "SINK")
+            .optflag("h", "help", "print this help");
+
+        let matches = options
+            .parse(args)
+            .map_err(|err| ConfigError::Arguments(err.to_string()))?;
+
+        if matches.opt_present("help") {
+            print!("{}", options.usage(USAGE));
+            return Ok(None);
+        }
+
+        let mut config = match matches.opt_str("config") {
+            Some(path) => Self::from_file(path)?,
+            None => Self::default(),
+        };
+
+        let listen = matches.opt_strs("listen");
+        let unix_sockets = matches.opt_strs("unix");
+        // Listeners given on the command line replace the configured ones
+        if !listen.is_empty() || !unix_sockets.is_empty() {
+            config.listen = listen;
+            config.unix_sockets = unix_sockets.into_iter().map(PathBuf::from).collect();
+        }
+
+        if let Some(workers) = matches.opt_str("workers") {
+            config.workers = workers.parse().map_err(|_| {
+                ConfigError::Arguments(format!("Invalid worker count: {}", workers))
+            })?;
+        }
+
+        if let Some(root) = matches.opt_str("static-root") {
+            config.static_root = Some(PathBuf::from(root));
+        }
+
+        if matches.opt_present("strict-hosts") {
+            config.strict_hosts = true;
+        }
+
+        if let Some(level) = matches.opt_str("log-level") {
+            config.logging.level = level;
+        }
+
+        if let Some(sink) = matches.opt_str("log-sink") {
+            config.logging.sink = sink;
+        }
+
+        config.validate()?;
+        Ok(Some(config))
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+
+        let content =
+            fs::read_to_string(path).map_err(|err| ConfigError::Read(path.to_path_buf(), err))?;
+
+        toml::from_str(&content).map_err(|err| ConfigError::Parse(path.to_path_buf(), err))
+    }
+
+    /// Check everything that could stop the server from starting
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |message: String| Err(ConfigError::Invalid(message));
+
+        if self.listen.is_empty() && self.unix_sockets.is_empty() {
+            return invalid("at least one listen address or unix socket is needed".to_string());
+        }
+
+        self.listen_addrs()?;
+        self.trusted_proxies()?;
+
+        if self.workers == 0 {
+            return invalid("workers needs to be at least 1".to_string());
+        }
+
+        if self.max_workers.is_some_and(|max| max < self.workers) {
+            return invalid("max_workers can't be less than workers".to_string());
+        }
+
+        if self.timeouts.request_read == 0
+            || self.timeouts.header_read == 0
+            || self.timeouts.keep_alive == 0
+        {
+            return invalid(
+                "request_read, header_read and keep_alive timeouts need to be at least 1"
+                    .to_string(),
+            );
+        }
+
+        let limits = &self.limits;
+        if limits.max_requests_per_connection == 0
+            || limits.max_request_line == 0
+            || limits.max_header_size == 0
+            || limits.max_headers == 0
+            || limits.max_queued == 0
+        {
+            return invalid("request limits need to be at least 1".to_string());
+        }
+
+        self.client_limits()?;
+
+        self.logging.level()?;
+        self.logging.module_levels()?;
+        self.logging.sink()?;
+
+        if let Some(access_log) = &self.access_log {
+            access_log.format()?;
+
+            if access_log.max_size == Some(0) || access_log.rotate_every == Some(0) {
+                return invalid("access log rotation needs to be at least 1".to_string());
+            }
+        }
+
+        if let Some(root) = &self.static_root {
+            validate_root(root)?;
+        }
+
+        let health_paths = self
+            .health
+            .iter()
+            .flat_map(|health| vec![&health.liveness_path, &health.readiness_path]);
+        for path in self.metrics_path.iter().chain(health_paths) {
+            if !path.starts_with('/') {
+                return invalid(format!("built-in path {} needs to start with /", path));
+            }
+        }
+        // The server would never be ready
+        if self.health.as_ref().map(|health| health.max_queued) == Some(Some(0)) {
+            return invalid("health.max_queued needs to be at least 1".to_string());
+        }
+
+        for proxy in self
+            .hosts
+            .iter()
+            .flat_map(|host| &host.proxies)
+            .chain(&self.proxies)
+        {
+            proxy.proxy()?;
+        }
+
+        let mut names = Vec::new();
+        for host in &self.hosts {
+            if host.names.is_empty() {
+                return invalid(format!("host for {:?} has no names", host.static_root));
+            }
+
+            for name in &host.names {
+                validate_host_name(name)?;
+
+                let name = name.to_lowercase();
+                if names.contains(&name) {
+                    return invalid(format!("host {} is configured more than once", name));
+                }
+                names.push(name);
+            }
+
+            validate_root(&host.static_root)?;
+        }
+
+        if let Some(tls) = &self.tls {
+            return invalid(format!(
+                "TLS ({:?}, {:?}) is not supported, terminate it in a reverse proxy in front of \
+                 the server",
+                tls.certificate, tls.key
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn listen_addrs(&self) -> Result<Vec<SocketAddr>, ConfigError> {
+        self.listen
+            .iter()
+            .map(|addr| {
+                addr.parse()
+                    .map_err(|_| ConfigError::Invalid(format!("invalid listen address: {}", addr)))
+            })
+            .collect()
+    }
+
+    pub fn trusted_proxies(&self) -> Result<Vec<IpAddr>, ConfigError> {
+        self.trusted_proxies
+            .iter()
+            .map(|addr| {
+                addr.parse().map_err(|_| {
+                    ConfigError::Invalid(format!("invalid trusted proxy address: {}", addr))
+                })
+            })
+            .collect()
+    }
+
+    /// Open the access log, if there is one
+    pub fn open_access_log(&self) -> Result<Option<AccessLog>, failure::Error> {
+        let config = match &self.access_log {
+            Some(config) => config,
+            None => return Ok(None),
+        };
+
+        let rotation = Rotation {
+            max_size: config.max_size,
+            interval: config.rotate_every.map(Duration::from_secs),
+        };
+
+        Ok(Some(AccessLog::open(
+            &config.path,
+            config.format()?,
+            rotation,
+        )?))
+    }
+
+    pub fn client_limits(&self) -> Result<ClientLimits, ConfigError> {
+        let clients = &self.clients;
+        let invalid = |message: &str| Err(ConfigError::Invalid(message.to_string()));
+
+        if clients.max_connections == Some(0) {
+            return invalid("clients.max_connections needs to be at least 1");
+        }
+        if clients
+            .requests_per_second
+            .is_some_and(|rate| !(rate > 0.0))
+        {
+            return invalid("clients.requests_per_second needs to be positive");
+        }
+        if clients.burst == 0 {
+            return invalid("clients.burst needs to be at least 1");
+        }
+        if clients.ipv6_prefix > 128 {
+            return invalid("clients.ipv6_prefix can be at most 128");
+        }
+
+        let allowlist = clients
+            .allowlist
+            .iter()
+            .map(|network| {
+                network
+                    .parse::<Network>()
+                    .map_err(|err| ConfigError::Invalid(format!("{}: {}", err, network)))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(ClientLimits {
+            max_connections: clients.max_connections,
+            requests_per_second: clients.requests_per_second,
+            burst: clients.burst,
+            ipv6_prefix: clients.ipv6_prefix,
+            allowlist,
+        })
+    }
+
+    pub fn health_checks(&self) -> Option<HealthChecks> {
+        self.health.as_ref().map(|health| HealthChecks {
+            liveness_path: health.liveness_path.clone(),
+            readiness_path: health.readiness_path.clone(),
+            max_queued: health.max_queued,
+        })
+    }
+
+    pub fn connection_settings(&self) -> ConnectionSettings {
+        ConnectionSettings {
+            request_read_timeout: Duration::from_secs(self.timeouts.request_read),
+            keep_alive_timeout: Duration::from_secs(self.timeouts.keep_alive),
+            header_read_timeout: Duration::from_secs(self.timeouts.header_read),
+            max_requests: self.limits.max_requests_per_connection,
+            max_request_line: self.limits.max_request_line,
+            max_header_size: self.limits.max_header_size,
+            max_headers: self.limits.max_headers,
+            max_queued: self.limits.max_queued,
+            drain_timeout: Duration::from_secs(self.timeouts.drain),
+            max_workers: self.max_workers,
+            worker_idle_timeout: Duration::from_secs(self.timeouts.worker_idle),
+            worker_stack_size: self.worker_stack_size,
+            worker_cpus: self.worker_cpus.clone(),
+            work_stealing: self.work_stealing,
+            // Checked by `validate`
+            trusted_proxies: self.trusted_proxies().unwrap_or_default(),
+        }
+    }
+}
+
+fn validate_root(root: &Path) -> Result<(), ConfigError> {
+    if root.is_dir() {
+        Ok(())
+    } else {
+        Err(ConfigError::Invalid(format!(
+            "static root {:?} is not a directory",
+            root
+        )))
+    }
+}
+
+/// Host names are domains, optionally starting with a "*." wildcard
+fn validate_host_name(name: &str) -> Result<(), ConfigError> {
+    let domain = if name.starts_with("*.") {
+        &name[2..]
+    } else {
+        name
+    };
+
+    let valid = !domain.is_empty()
+        && domain.split('.').all(|label| {
+            !label.is_empty() && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        });
+
+    if valid {
+        Ok(())
+    } else {
+        Err(ConfigError::Invalid(format!("invalid host name: {}", name)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    fn parse(content: &str) -> Config {
+        toml::from_str(content).unwrap()
+    }
+
+    /// Message of the error `validate` gives for `content`
+    fn invalid(content: &str) -> String {
+        match parse(content).validate() {
+            Err(ConfigError::Invalid(message)) => message,
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    fn from_args(args: &[&str]) -> Result<Option<Config>, ConfigError> {
+        let args: Vec<_> = args.iter().map(|arg| arg.to_string()).collect();
+        Config::from_args(&args)
+    }
+
+    #[test]
+    fn validate_limits() {
+        assert!(parse("").validate().is_ok());
+        assert!(parse("[limits]\nmax_queued = 1\n").validate().is_ok());
+
+        // Every connection would be refused with a 503
+        match parse("[limits]\nmax_queued = 0\n").validate() {
+            Err(ConfigError::Invalid(message)) => {
+                assert_eq!(message, "request limits need to be at least 1")
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(parse("[limits]\nmax_headers = 0\n").validate().is_err());
+    }
+
+    #[test]
+    fn work_stealing() {
+        assert!(!parse("").connection_settings().work_stealing);
+        assert!(
+            parse("work_stealing = true\n")
+                .connection_settings()
+                .work_stealing
+        );
+    }
+
+    #[test]
+    fn validate_listeners() {
+        assert_eq!(
+            invalid("listen = [\"localhost:80\"]\n"),
+            "invalid listen address: localhost:80"
+        );
+        assert_eq!(
+            invalid("listen = []\n"),
+            "at least one listen address or unix socket is needed"
+        );
+        assert!(
+            parse("listen = []\nunix_sockets = [\"/run/milton.sock\"]\n")
+                .validate()
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn validate_workers() {
+        assert_eq!(invalid("workers = 0\n"), "workers needs to be at least 1");
+        assert_eq!(
+            invalid("workers = 8\nmax_workers = 4\n"),
+            "max_workers can't be less than workers"
+        );
+        assert!(parse("workers = 8\nmax_workers = 8\n").validate().is_ok());
+    }
+
+    #[test]
+    fn validate_hosts() {
+        let root = env::temp_dir();
+        let hosts =
+            |names: &str| format!("[[hosts]]\nnames = [{}]\nstatic_root = {:?}\n", names, root);
+
+        assert!(parse(&hosts("\"example.com\", \"*.example.com\""))
+            .validate()
+            .is_ok());
+        assert_eq!(
+            invalid(&format!(
+                "{}{}",
+                hosts("\"example.com\""),
+                hosts("\"Example.com\"")
+            )),
+            "host example.com is configured more than once"
+        );
+        for name in &["exa mple.com", "example..com", "*.", "example.com:80"] {
+            assert_eq!(
+                invalid(&hosts(&format!("{:?}", name))),
+                format!("invalid host name: {}", name)
+            );
+        }
+    }
+
+    #[test]
+    fn validate_logging() {
+        assert_eq!(
+            invalid("[logging]\nlevel = \"loud\"\n"),
+            "invalid log level: loud"
+        );
+        assert_eq!(
+            invalid("[logging.modules]\n\"http_server\" = \"loud\"\n"),
+            "invalid log level: loud"
+        );
+        assert_eq!(
+            invalid("[logging]\nsink = \"printer\"\n"),
+            "invalid log sink: printer"
+        );
+        assert_eq!(
+            invalid("[logging]\nsink = \"file\"\n"),
+            "the file log sink needs a path"
+        );
+    }
+
+    #[test]
+    fn validate_tls() {
+        let message = invalid("[tls]\ncertificate = \"server.pem\"\nkey = \"server.key\"\n");
+        assert!(message.starts_with("TLS (\"server.pem\", \"server.key\") is not supported"));
+    }
+
+    #[test]
+    fn validate_builtin_paths() {
+        assert!(parse("metrics_path = \"/metrics\"\n[health]\n")
+            .validate()
+            .is_ok());
+        assert_eq!(
+            invalid("metrics_path = \"metrics\"\n"),
+            "built-in path metrics needs to start with /"
+        );
+        assert_eq!(
+            invalid("[health]\nreadiness_path = \"readyz\"\n"),
+            "built-in path readyz needs to start with /"
+        );
+        assert_eq!(
+            invalid("[health]\nmax_queued = 0\n"),
+            "health.max_queued needs to be at least 1"
+        );
+    }
+
+    #[test]
+    fn command_line_listeners() {
+        let path = env::temp_dir().join(format!("milton-config-{}.toml", process::id()));
+        fs::write(
+            &path,
+            "listen = [\"0.0.0.0:80\"]\nunix_sockets = [\"/run/milton.sock\"]\n",
+        ).unwrap();
+        let config_path = path.to_str().unwrap();
+
+        let config = from_args(&["-c", config_path]).unwrap().unwrap();
+        assert_eq!(config.listen, ["0.0.0.0:80"]);
+        assert_eq!(config.unix_sockets, [PathBuf::from("/run/milton.sock")]);
+
+        // Either flag replaces both kinds of configured listeners
+        let config = from_args(&["-c", config_path, "--listen", "127.0.0.1:8080"])
+            .unwrap()
+            .unwrap();
+        assert_eq!(config.listen, ["127.0.0.1:8080"]);
+        assert!(config.unix_sockets.is_empty());
+
+        let config = from_args(&["-c", config_path, "--unix", "/tmp/milton.sock"])
+            .unwrap()
+            .unwrap();
+        assert!(config.listen.is_empty());
+        assert_eq!(config.unix_sockets, [PathBuf::from("/tmp/milton.sock")]);
+
+        fs::remove_file(&path).unwrap();
+
+        // Flags are validated like the configuration file
+        match from_args(&["--listen", "localhost"]) {
+            Err(ConfigError::Invalid(message)) => {
+                assert_eq!(message, "invalid listen address: localhost")
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
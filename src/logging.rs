@@ -0,0 +1,260 @@
+//! Logger for every crate of the workspace, which all log through the `log` facade.
+//!
+//! Lines are written as key-value pairs (logfmt), e.g.
+//! `ts=2018-10-20T15:30:00.000-04:00 level=info module=http_server::event_loop msg="Reloaded hosts"`
+//! Fields given to the log macros (`info!(pid = 42; "...")`) are appended to the line, followed by
+//! the ID of the request being handled, if any.
+
+use crate::config::{LogSink, Logging};
+use chrono::{Local, SecondsFormat};
+use log::kv::{Key, Value, VisitSource};
+use log::{Level, LevelFilter, Metadata, Record};
+use std::fmt::{Display, Write as FmtWrite};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::net::UnixDatagram;
+use std::process;
+use std::sync::Mutex;
+
+/// Socket of the local syslog daemon
+const SYSLOG_SOCKET: &str = "/dev/log";
+/// Syslog facility for system daemons
+const SYSLOG_DAEMON: u8 = 3;
+
+enum Sink {
+    Stderr,
+    File(Mutex<File>),
+    Syslog(UnixDatagram),
+    /// stderr, with the priority prefixes understood by journald (see sd-daemon(3))
+    Journald,
+}
+
+pub struct Logger {
+    level: LevelFilter,
+    /// Levels by module, the most specific modules come first
+    modules: Vec<(String, LevelFilter)>,
+    sink: Sink,
+}
+
+impl Logger {
+    pub fn new(config: &Logging) -> Result<Self, failure::Error> {
+        let mut modules = config.module_levels()?;
+        modules.sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()));
+
+        let sink = match config.sink()? {
+            LogSink::Stderr => Sink::Stderr,
+            LogSink::File(path) => Sink::File(Mutex::new(
+                OpenOptions::new().create(true).append(true).open(path)?,
+            )),
+            LogSink::Syslog => {
+                let socket = UnixDatagram::unbound()?;
+                socket.connect(SYSLOG_SOCKET)?;
+                Sink::Syslog(socket)
+            }
+            LogSink::Journald => Sink::Journald,
+        };
+
+        Ok(Self {
+            level: config.level()?,
+            modules,
+            sink,
+        })
+    }
+
+    /// Make this the logger of the process
+    pub fn install(self) -> Result<(), log::SetLoggerError> {
+        // Records are only filtered per module once they reach the logger
+        let max_level = self
+            .modules
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.level, |max, level| max.max(level));
+
+        log::set_boxed_logger(Box::new(self))?;
+        log::set_max_level(max_level);
+        Ok(())
+    }
+
+    /// Level of the most specific module configured for `target`
+    fn level_for(&self, target: &str) -> LevelFilter {
+        self.modules
+            .iter()
+            .find(|(module, _)| {
+                target == module
+                    || (target.starts_with(module.as_str())
+                        && target[module.len()..].starts_with("::"))
+            })
+            .map(|(_, level)| *level)
+            .unwrap_or(self.level)
+    }
+
+    fn write(&self, record: &Record) -> io::Result<()> {
+        match &self.sink {
+            Sink::Stderr => io::stderr().write_all(format_record(record, true).as_bytes()),
+            Sink::File(file) => file
+                .lock()
+                .unwrap()
+                .write_all(format_record(record, true).as_bytes()),
+            Sink::Syslog(socket) => {
+                let priority = SYSLOG_DAEMON * 8 + severity(record.level());
+                let message = format!(
+                    "<{}>milton[{}]: {}",
+                    priority,
+                    process::id(),
+                    format_record(record, false)
+                );
+                socket.send(message.as_bytes()).map(|_| ())
+            }
+            Sink::Journald => {
+                let line = format!(
+                    "<{}>{}",
+                    severity(record.level()),
+                    format_record(record, false)
+                );
+                io::stderr().write_all(line.as_bytes())
+            }
+        }
+    }
+}
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level_for(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            // There is nowhere else to report this
+            let _ = self.write(record);
+        }
+    }
+
+    fn flush(&self) {
+        if let Sink::File(file) = &self.sink {
+            let _ = file.lock().unwrap().flush();
+        }
+    }
+}
+
+/// Syslog severity of a level
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// Format a record as a logfmt line
+/// Syslog and journald timestamp lines themselves, so `timestamp` is false for them
+fn format_record(record: &Record, timestamp: bool) -> String {
+    let mut line = String::new();
+
+    if timestamp {
+        let _ = write!(
+            line,
+            "ts={} ",
+            Local::now().to_rfc3339_opts(SecondsFormat::Millis, false)
+        );
+    }
+
+    let _ = write!(
+        line,
+        "level={} module={} msg={}",
+        record.level().to_string().to_lowercase(),
+        record.target(),
+        quote(record.args())
+    );
+
+    let _ = record.key_values().visit(&mut Fields(&mut line));
+
+    if let Some(request_id) = http_server::current_request_id() {
+        let _ = write!(line, " request_id={}", quote(request_id));
+    }
+
+    line.push('\n');
+    line
+}
+
+/// Appends the fields of a record to a line
+struct Fields<'a>(&'a mut String);
+
+impl<'a, 'kvs> VisitSource<'kvs> for Fields<'a> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), log::kv::Error> {
+        let _ = write!(self.0, " {}={}", key, quote(value));
+        Ok(())
+    }
+}
+
+/// Quote a value if it would otherwise not be read back as a single value
+fn quote(value: impl Display) -> String {
+    let value = value.to_string();
+
+    let needs_quotes = value.is_empty()
+        || value
+            .chars()
+            .any(|c| c.is_whitespace() || c == '=' || c == '"' || c.is_control());
+
+    if needs_quotes {
+        format!("{:?}", value)
+    } else {
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn module_levels() {
+        let mut config = Logging::default();
+        config
+            .modules
+            .insert("http_server".to_string(), "warn".to_string());
+        config
+            .modules
+            .insert("http_server::event_loop".to_string(), "debug".to_string());
+        let logger = Logger::new(&config).unwrap();
+
+        assert_eq!(logger.level_for("milton"), LevelFilter::Info);
+        assert_eq!(logger.level_for("http_server"), LevelFilter::Warn);
+        assert_eq!(
+            logger.level_for("http_server::connection"),
+            LevelFilter::Warn
+        );
+        assert_eq!(
+            logger.level_for("http_server::event_loop"),
+            LevelFilter::Debug
+        );
+        assert_eq!(logger.level_for("http_server_extra"), LevelFilter::Info);
+    }
+
+    #[test]
+    fn format_fields() {
+        let line = format_record(
+            &Record::builder()
+                .args(format_args!("Started upgraded server"))
+                .level(Level::Info)
+                .target("http_server::event_loop")
+                .key_values(&[("pid", 42), ("listeners", 2)])
+                .build(),
+            false,
+        );
+
+        assert_eq!(
+            line,
+            "level=info module=http_server::event_loop msg=\"Started upgraded server\" pid=42 \
+             listeners=2\n"
+        );
+    }
+
+    #[test]
+    fn quote_values() {
+        assert_eq!(quote("value"), "value");
+        assert_eq!(quote(""), "\"\"");
+        assert_eq!(quote("two words"), "\"two words\"");
+        assert_eq!(quote("say \"hi\""), "\"say \\\"hi\\\"\"");
+    }
+}
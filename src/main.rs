@@ -7,40 +7,30 @@ extern crate log;
 #[macro_use]
 extern crate http;
 
-use chrono::prelude::*;
+#[macro_use]
+extern crate failure;
+
+mod config;
+mod logging;
+mod static_files;
+
+use crate::config::Config;
+use crate::logging::Logger;
+use crate::static_files::StaticDirectory;
 use core::time::Duration;
 use http::{compress_html, gzip};
-use http_server::HttpRouteInfo;
-use log::{Level, LevelFilter, Metadata, Record};
+use http_server::{HttpRouteInfo, HttpServer, Listener, VirtualHosts};
 use router::{Endpoint, RoutedInfo};
-use std::io::Write;
+use std::env;
+use std::process;
 use std::thread;
 
-struct Logger;
-
-impl log::Log for Logger {
-    fn enabled(&self, metadata: &Metadata) -> bool {
-        metadata.level() <= Level::Info
-    }
-
-    fn log(&self, record: &Record) {
-        if self.enabled(record.metadata()) {
-            let datetime = Local::now();
-            println!("[{:?}] - {} - {}", datetime, record.level(), record.args());
-        }
-    }
-
-    fn flush(&self) {}
-}
-
-static LOGGER: Logger = Logger;
-
 /// Endpoint to serve static content
 struct StaticResource(Vec<u8>);
 
 impl Endpoint<HttpRouteInfo, ()> for StaticResource {
-    fn process(&self, mut route_info: RoutedInfo<HttpRouteInfo>) {
-        route_info.data.writer().write_all(&self.0).unwrap();
+    fn process(&self, route_info: RoutedInfo<HttpRouteInfo>) {
+        route_info.data.prerendered(&self.0).unwrap();
     }
 }
 
@@ -58,33 +48,119 @@ impl Endpoint<HttpRouteInfo, ()> for Page404 {
     }
 }
 
+/// Set up the routers of the default host and of every configured host
+fn configure_hosts(hosts: &mut VirtualHosts, config: &Config) -> Result<(), failure::Error> {
+    hosts.set_strict(config.strict_hosts);
+
+    let default = hosts.default_mut();
+    match &config.static_root {
+        Some(root) => default.add_path("", StaticDirectory::load(root, config.cache.max_age)?),
+        None => {
+            default.add_path(
+                "/",
+                StaticResource(include_bytes!("../static_out/landing_page_html.http").to_vec()),
+            );
+            default.add_path(
+                "/favicon.ico",
+                StaticResource(include_bytes!("../static_out/favicon_ico.http").to_vec()),
+            );
+        }
+    }
+    default.set_endpoint_404(Page404::create());
+    for proxy in &config.proxies {
+        default.add_path(proxy.route(), proxy.proxy()?);
+    }
+
+    for host in &config.hosts {
+        let directory = StaticDirectory::load(&host.static_root, config.cache.max_age)?;
+        for name in &host.names {
+            let router = hosts.host_mut(name);
+            router.add_path("", directory.clone());
+            router.set_endpoint_404(Page404::create());
+            for proxy in &host.proxies {
+                router.add_path(proxy.route(), proxy.proxy()?);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// `args` are the command line arguments the configuration was read from, they are read again
+/// when the server is reloaded
+fn create_server(
+    config: &Config,
+    args: Vec<String>,
+    inherited: Vec<Listener>,
+) -> Result<HttpServer, failure::Error> {
+    let mut server = if inherited.is_empty() {
+        let mut server = HttpServer::bind(&config.listen_addrs()?)?;
+        for path in &config.unix_sockets {
+            server.add_listener(Listener::bind_unix(path)?);
+        }
+        server
+    } else {
+        info!(count = inherited.len(); "Using inherited listening sockets");
+        HttpServer::from_listeners(inherited)
+    };
+
+    *server.settings_mut() = config.connection_settings();
+    server.set_client_limits(config.client_limits()?);
+    if let Some(access_log) = config.open_access_log()? {
+        server.set_access_log(access_log);
+    }
+    if let Some(path) = &config.metrics_path {
+        server.set_metrics_path(path);
+    }
+    if let Some(checks) = config.health_checks() {
+        server.set_health_checks(checks);
+    }
+    configure_hosts(server.virtual_hosts_mut(), config)?;
+
+    server.on_reload(move || {
+        let config =
+            Config::from_args(&args)?.ok_or_else(|| format_err!("No configuration to reload"))?;
+
+        let mut hosts = VirtualHosts::default();
+        configure_hosts(&mut hosts, &config)?;
+        Ok(hosts)
+    });
+
+    Ok(server)
+}
+
 fn main() {
-    log::set_logger(&LOGGER)
-        .map(|()| log::set_max_level(LevelFilter::Info))
-        .unwrap();
+    let args: Vec<String> = env::args().skip(1).collect();
+    let config = match Config::from_args(&args) {
+        Ok(Some(config)) => config,
+        Ok(None) => return,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(2);
+        }
+    };
+
+    let logger = Logger::new(&config.logging).unwrap_or_else(|err| {
+        eprintln!("Could not set up logging: {}", err);
+        process::exit(2);
+    });
+    logger.install().unwrap();
 
     info!("Server started...");
 
-    loop {
-        let mut server = http_server::HttpServer::create(80).unwrap();
+    // Listening sockets handed over by a previous instance of the server (or systemd)
+    let mut inherited = http_server::inherited_listeners();
 
-        server.add_route(
-            "/",
-            StaticResource(include_bytes!("../static_out/landing_page_html.http").to_vec()),
-        );
-        server.add_route(
-            "/favicon.ico",
-            StaticResource(include_bytes!("../static_out/favicon_ico.http").to_vec()),
+    loop {
+        let result = create_server(&config, args.clone(), inherited.drain(..).collect()).and_then(
+            |server| {
+                server.listen(config.workers)?;
+                Ok(())
+            },
         );
 
-        server.router_mut().set_endpoint_404(Page404::create());
-
-        let result = server.listen(40);
         if let Err(err) = result {
-            error!(
-                "Server ended in error, starting it up again in 5 seconds. Error: {:?}",
-                err
-            );
+            error!(error:% = err; "Server ended in error, starting it up again in 5 seconds");
             thread::sleep(Duration::from_secs(5));
         } else {
             break;
@@ -0,0 +1,130 @@
+use http::{compress_html, gzip, ResponseBuilder};
+use http_server::HttpRouteInfo;
+use router::{Endpoint, RoutedInfo};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use walkdir::WalkDir;
+
+/// Endpoint serving every file of a directory
+///
+/// Files are read, compressed and turned into full responses once when the directory is loaded,
+/// the same way `pre_build.rs` does for the pages built into the binary.
+/// Clones share the loaded responses.
+#[derive(Clone)]
+pub struct StaticDirectory {
+    /// Responses by request path, e.g. "/blog/index.html"
+    responses: Arc<HashMap<String, Vec<u8>>>,
+    not_found: Arc<Vec<u8>>,
+}
+
+impl StaticDirectory {
+    /// Load every file under `root`, clients may cache them for `max_age` seconds
+    pub fn load(root: &Path, max_age: u64) -> io::Result<Self> {
+        let mut responses = HashMap::new();
+
+        for entry in WalkDir::new(root) {
+            let entry = entry?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+
+            let relative = entry.path().strip_prefix(root).unwrap();
+            let path = relative
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+
+            let extension = entry
+                .path()
+                .extension()
+                .map(|extension| extension.to_string_lossy().to_lowercase())
+                .unwrap_or_default();
+
+            let content = fs::read(entry.path())?;
+            responses.insert(format!("/{}", path), render(&extension, content, max_age));
+        }
+
+        Ok(Self {
+            responses: Arc::new(responses),
+            not_found: Arc::new(compress_html("Could not find page")),
+        })
+    }
+
+    fn find(&self, path: &str) -> Option<&Vec<u8>> {
+        // The query is not used to find files
+        let path = path.split('?').next().unwrap_or(path);
+
+        if path.ends_with('/') {
+            return self.responses.get(&format!("{}index.html", path));
+        }
+
+        self.responses
+            .get(path)
+            .or_else(|| self.responses.get(&format!("{}/index.html", path)))
+    }
+}
+
+impl Endpoint<HttpRouteInfo, ()> for StaticDirectory {
+    fn use_strict_path_matching(&self) -> bool {
+        false
+    }
+
+    fn process(&self, route_info: RoutedInfo<HttpRouteInfo>) {
+        let result = match self.find(route_info.data.request().path()) {
+            Some(response) => route_info.data.prerendered(response),
+            None => route_info.data.not_found_404(&self.not_found),
+        };
+
+        if let Err(err) = result {
+            error!(error:% = err; "Could not send static file");
+        }
+    }
+}
+
+/// Build the full response for a file
+fn render(extension: &str, content: Vec<u8>, max_age: u64) -> Vec<u8> {
+    // Formats that are already compressed are sent as is
+    let (content_type, compress) = match extension {
+        "html" | "htm" => ("text/html charset=UTF-8", true),
+        "css" => ("text/css", true),
+        "js" => ("application/javascript", true),
+        "json" => ("application/json", true),
+        "txt" => ("text/plain; charset=UTF-8", true),
+        "xml" => ("application/xml", true),
+        "svg" => ("image/svg+xml", true),
+        "ico" => ("image/x-icon", true),
+        "png" => ("image/png", false),
+        "jpg" | "jpeg" => ("image/jpeg", false),
+        "gif" => ("image/gif", false),
+        "woff" => ("font/woff", false),
+        "woff2" => ("font/woff2", false),
+        "pdf" => ("application/pdf", false),
+        _ => ("application/octet-stream", false),
+    };
+
+    let body = match extension {
+        "html" | "htm" => compress_html(&String::from_utf8_lossy(&content)),
+        _ if compress => gzip(&content),
+        _ => content,
+    };
+
+    let mut response = ResponseBuilder::ok_200();
+    response
+        .header("Content-Type", content_type)
+        .header("Cache-Control", &format!("max-age={}", max_age))
+        .header("Cache-Control", "public");
+    if compress {
+        response.header("Content-Encoding", "gzip");
+    }
+    let response = response.build();
+
+    let mut rendered = response.head_bytes();
+    rendered.extend_from_slice(format!("Content-Length:{}\r\n\r\n", body.len()).as_bytes());
+    rendered.extend_from_slice(&body);
+
+    rendered
+}